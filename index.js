@@ -1,4 +1,4 @@
-const { execSync } = require("node:child_process");
+const { execFileSync, execSync } = require("node:child_process");
 const fs = require("fs");
 const path = require("path");
 const https = require("https");
@@ -245,15 +245,20 @@ function makeExecutable(binaryPath) {
   }
 }
 
-async function run() {
+async function run(args = []) {
   try {
     const binaryPath = await getBinary();
     makeExecutable(binaryPath);
-    execSync(binaryPath, { stdio: "inherit" });
+    execFileSync(binaryPath, args, { stdio: "inherit" });
   } catch (error) {
     console.error(`Error: ${error.message}`);
-    process.exit(1);
+    // The binary's exit code tells what kind of failure it was
+    process.exit(error.status ?? 1);
   }
 }
 
-run();
+module.exports = { run };
+
+if (require.main === module) {
+  run();
+}
@@ -0,0 +1,4 @@
+const { run } = require("./index.js");
+
+// Removes the secret files written by the main step
+run(["--post"]);
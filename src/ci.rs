@@ -1,8 +1,126 @@
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    debug,
+    error::{ErrorKind, ResultExt},
+    summary::{self, SummaryEntry},
+};
+
+mod azure;
+mod collecting;
+mod gitlab;
+mod layered;
+mod local;
+mod masking;
+mod output_only;
+mod protecting;
+
+pub use azure::AzurePipelinesRunner;
+pub use collecting::Collecting;
+pub use gitlab::GitlabCiRunner;
+pub use layered::{Layer, Layered};
+pub use local::{LocalRunner, OutputFormat};
+pub use masking::Masking;
+pub use output_only::OutputOnly;
+pub use protecting::Protecting;
+
+/// The CI systems the action knows how to talk to.
+#[derive(Debug, PartialEq)]
+pub enum CiBackend {
+    GithubActions,
+    GitlabCi,
+    AzurePipelines,
+    /// Not running inside any known CI system.
+    None,
+}
+
+impl CiBackend {
+    /// Detects the CI system from the variables it sets for every job.
+    pub fn detect() -> Self {
+        Self::detect_with(get_var)
+    }
+
+    fn detect_with(get_var: impl Fn(&str) -> Option<String>) -> Self {
+        if get_var("GITLAB_CI").is_some() {
+            CiBackend::GitlabCi
+        } else if get_var("TF_BUILD").is_some() {
+            CiBackend::AzurePipelines
+        } else if get_var("GITHUB_ACTIONS").is_some() {
+            CiBackend::GithubActions
+        } else {
+            CiBackend::None
+        }
+    }
+}
+
+/// Writes a value to a file that only the current user can read, creating parent directories
+/// as needed.
+pub fn write_private_file(path: &Path, value: &str) -> Result<PathBuf> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
 
-use anyhow::Result;
+    let mut file = open_private(path, false)?;
+    file.write_all(value.as_bytes())?;
+    file.flush()?;
+    Ok(path.to_path_buf())
+}
+
+/// Opens a file for writing that only the current user can read, creating it if needed. The
+/// file is appended to if `append` is set, and truncated otherwise.
+pub fn open_private(path: &Path, append: bool) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
 
-use crate::debug;
+    let file = options.open(path)?;
+
+    // The mode only applies to newly created files, so tighten existing ones as well
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    Ok(file)
+}
+
+/// Checks that a name can be used as an environment variable in a shell: it must only contain
+/// ASCII letters, digits and underscores, and must not start with a digit.
+pub fn validate_shell_variable_name(name: &str) -> Result<()> {
+    let Some(first) = name.chars().next() else {
+        bail!("The variable name is empty");
+    };
+    if first.is_ascii_digit() {
+        bail!("'{name}' is not a valid variable name, as it starts with a digit");
+    }
+    if let Some(invalid) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+    {
+        bail!(
+            "'{name}' is not a valid variable name, as it contains '{invalid}'. Only letters, digits and underscores are allowed"
+        );
+    }
+    Ok(())
+}
+
+/// Prefer this over `std::env::var` to ensure that vars are both set and not empty to avoid
+/// unintended errors.
+fn get_var(key: &str) -> Option<String> {
+    match std::env::var(key) {
+        Ok(value) if !value.trim().is_empty() => Some(value),
+        _ => None,
+    }
+}
 
 pub trait ContinuousIntegration {
     /// A function for returning the value for an input by the given name.
@@ -17,8 +135,71 @@ pub trait ContinuousIntegration {
     /// Masks a value in the CI logs to prevent it from being displayed.
     /// In some CI systems this may not be possible. In which case this function may be a no-op.
     fn mask_value(&mut self, value: &str);
+
+    /// Checks that a name can be used for a variable in this CI system.
+    fn validate_variable_name(&self, name: &str) -> Result<()> {
+        validate_shell_variable_name(name)
+    }
+
+    /// Checks that a name can be used for a value only set as an output, never in the
+    /// environment. Outputs are variables themselves in most CI systems.
+    fn validate_output_name(&self, name: &str) -> Result<()> {
+        self.validate_variable_name(name)
+    }
+
+    /// Writes a secret to a file only readable by the current user and returns its path.
+    /// Relative paths are resolved against a temporary directory outside of the workspace.
+    fn write_secret_file(&mut self, path: &Path, value: &str) -> Result<PathBuf> {
+        write_private_file(&std::env::temp_dir().join(path), value)
+    }
+
+    /// The file in which to record the secret files written, for the post step to remove them.
+    /// CI systems without a post step return `None`.
+    fn file_manifest_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Reports progress in the job log.
+    fn log(&mut self, message: &str) {
+        println!("{message}");
+    }
+
+    /// Starts a collapsible section of the job log, which lasts until `end_group`.
+    /// CI systems without sections just log its name.
+    fn start_group(&mut self, name: &str) {
+        self.log(name);
+    }
+
+    /// Ends the section started by `start_group`, if there is one.
+    fn end_group(&mut self) {}
+
+    /// Reports a warning that should stand out from the regular log output, ideally in the UI
+    /// of the CI system under the given title.
+    fn warning(&mut self, _title: &str, message: &str) {
+        eprintln!("Warning: {message}");
+    }
+
+    /// Reports the error that failed the step, ideally in the UI of the CI system under the given
+    /// title.
+    fn error(&mut self, _title: &str, message: &str) {
+        eprintln!("Error: {message}");
+    }
+
+    /// Reports which secrets were delivered and how, without their values, where the CI system
+    /// can show it alongside the job. CI systems without such a report do nothing.
+    fn write_summary(&mut self, _entries: &[SummaryEntry]) -> Result<()> {
+        Ok(())
+    }
 }
 
+const FILE_MANIFEST_NAME: &str = "sm-action-files";
+
+/// GitHub reserves variables starting with this prefix, and ignores attempts to set them.
+const GITHUB_RESERVED_PREFIX: &str = "GITHUB_";
+
+/// Lines of multi-line secrets shorter than this aren't masked on their own.
+const MIN_MASKED_LINE_LENGTH: usize = 8;
+
 pub struct GithubActionsRunner<W: Write> {
     env_file: W,
     output_file: W,
@@ -34,13 +215,21 @@ impl<W: Write> GithubActionsRunner<W> {
         Ok(())
     }
 
-    /// Prefer this over `std::env::var` to ensure that vars are both set and not empty to avoid
-    /// unintended errors.
-    fn get_var(key: &str) -> Option<String> {
-        match std::env::var(key) {
-            Ok(value) if !value.trim().is_empty() => Some(value),
-            _ => None,
+    /// Builds the `::add-mask::` commands for a value. The runner masks logs line by line, so
+    /// each line of a multi-line value is masked on its own as well, as long as it is long
+    /// enough not to mask unrelated output.
+    fn mask_commands(&self, value: &str) -> Vec<String> {
+        let mut commands = vec![format!("::add-mask::{}", self.escape_secret(value))];
+
+        if value.contains('\n') {
+            for line in value.lines().map(str::trim) {
+                if line.len() >= MIN_MASKED_LINE_LENGTH {
+                    commands.push(format!("::add-mask::{}", self.escape_secret(line)));
+                }
+            }
         }
+
+        commands
     }
 
     fn escape_secret(&self, value: &str) -> String {
@@ -49,32 +238,47 @@ impl<W: Write> GithubActionsRunner<W> {
             .replace('\r', "%0D")
             .replace('\n', "%0A")
     }
+
+    /// Builds a `::warning::` or `::error::` command, which GitHub shows as an annotation on the
+    /// workflow run. Properties such as the title may not contain the `:` and `,` separators.
+    fn annotation_command(&self, command: &str, title: &str, message: &str) -> String {
+        let title = self
+            .escape_secret(title)
+            .replace(':', "%3A")
+            .replace(',', "%2C");
+        format!("::{command} title={title}::{}", self.escape_secret(message))
+    }
 }
 
 impl GithubActionsRunner<std::fs::File> {
     pub fn new() -> Result<GithubActionsRunner<std::fs::File>> {
-        let env_path = std::env::var("GITHUB_ENV").expect("GITHUB_ENV must be set");
-
-        debug!("Writing to GITHUB_ENV: {env_path}");
-
-        let env_file = OpenOptions::new().append(true).open(&env_path)?;
-
-        let output_path = std::env::var("GITHUB_OUTPUT").expect("GITHUB_OUTPUT must be set");
-
-        debug!("Writing to GITHUB_OUTPUT: {env_path}");
-
-        let output_file = OpenOptions::new().append(true).open(&output_path)?;
+        let env_file = Self::open_file_command("GITHUB_ENV").kind(ErrorKind::Config)?;
+        let output_file = Self::open_file_command("GITHUB_OUTPUT").kind(ErrorKind::Config)?;
         Ok(Self {
             env_file,
             output_file,
         })
     }
+
+    /// Opens the file named by a file command variable, e.g. `GITHUB_ENV`, for appending.
+    fn open_file_command(variable: &str) -> Result<std::fs::File> {
+        let Some(path) = get_var(variable) else {
+            bail!("{variable} is not set. Is this running as a step of a GitHub Actions job?");
+        };
+
+        debug!("Writing to {variable}: {path}");
+
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {variable} ('{path}').\nError: {e}"))
+    }
 }
 
 impl<W: Write> ContinuousIntegration for GithubActionsRunner<W> {
     fn get_input(&self, name: &str) -> Option<String> {
         let upper_name = name.to_ascii_uppercase();
-        Self::get_var(&format!("INPUT_{upper_name}")).to_owned()
+        get_var(&format!("INPUT_{upper_name}"))
     }
 
     fn set_environment(&mut self, name: &str, value: &str) -> Result<()> {
@@ -87,8 +291,82 @@ impl<W: Write> ContinuousIntegration for GithubActionsRunner<W> {
 
     /// Masks a value in the GitHub Actions logs to prevent it from being displayed.
     fn mask_value(&mut self, value: &str) {
-        let escaped_secret = Self::escape_secret(self, value);
-        println!("::add-mask::{escaped_secret}");
+        for command in self.mask_commands(value) {
+            println!("{command}");
+        }
+    }
+
+    fn validate_variable_name(&self, name: &str) -> Result<()> {
+        validate_shell_variable_name(name)?;
+        if name
+            .to_ascii_uppercase()
+            .starts_with(GITHUB_RESERVED_PREFIX)
+        {
+            bail!(
+                "'{name}' is not a valid variable name, as GitHub reserves the '{GITHUB_RESERVED_PREFIX}' prefix"
+            );
+        }
+        Ok(())
+    }
+
+    /// Outputs only need to be usable as `steps.<id>.outputs.<name>`, which also allows dashes.
+    fn validate_output_name(&self, name: &str) -> Result<()> {
+        let Some(first) = name.chars().next() else {
+            bail!("The output name is empty");
+        };
+        if !first.is_ascii_alphabetic() && first != '_' {
+            bail!("'{name}' is not a valid output name, as it must start with a letter or '_'");
+        }
+        if let Some(invalid) = name
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '-'))
+        {
+            bail!(
+                "'{name}' is not a valid output name, as it contains '{invalid}'. Only letters, digits, '-' and '_' are allowed"
+            );
+        }
+        Ok(())
+    }
+
+    /// Relative paths are resolved against `RUNNER_TEMP`, which is emptied after every job.
+    fn write_secret_file(&mut self, path: &Path, value: &str) -> Result<PathBuf> {
+        let temp_dir = get_var("RUNNER_TEMP")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        write_private_file(&temp_dir.join(path), value)
+    }
+
+    fn file_manifest_path(&self) -> Option<PathBuf> {
+        get_var("RUNNER_TEMP").map(|temp_dir| PathBuf::from(temp_dir).join(FILE_MANIFEST_NAME))
+    }
+
+    fn start_group(&mut self, name: &str) {
+        println!("::group::{}", self.escape_secret(name));
+    }
+
+    fn end_group(&mut self) {
+        println!("::endgroup::");
+    }
+
+    fn warning(&mut self, title: &str, message: &str) {
+        println!("{}", self.annotation_command("warning", title, message));
+    }
+
+    fn error(&mut self, title: &str, message: &str) {
+        println!("{}", self.annotation_command("error", title, message));
+    }
+
+    /// Appends a table to the job summary in `GITHUB_STEP_SUMMARY`, if the runner provides one.
+    fn write_summary(&mut self, entries: &[SummaryEntry]) -> Result<()> {
+        let Some(summary_path) = get_var("GITHUB_STEP_SUMMARY") else {
+            return Ok(());
+        };
+        debug!("Writing job summary to {summary_path}");
+
+        let mut summary_file = OpenOptions::new().append(true).open(&summary_path)?;
+        summary_file.write_all(summary::render_markdown(entries).as_bytes())?;
+        summary_file.flush()?;
+        Ok(())
     }
 }
 
@@ -124,6 +402,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_detect_ci_backend() {
+        let detect = |vars: &[&str]| {
+            CiBackend::detect_with(|key| vars.contains(&key).then(|| "true".to_string()))
+        };
+
+        assert_eq!(detect(&["GITHUB_ACTIONS"]), CiBackend::GithubActions);
+        assert_eq!(detect(&["GITLAB_CI"]), CiBackend::GitlabCi);
+        assert_eq!(detect(&["TF_BUILD"]), CiBackend::AzurePipelines);
+        assert_eq!(detect(&[]), CiBackend::None);
+    }
+
+    #[test]
+    fn test_annotation_command() {
+        let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
+            env_file: vec![],
+            output_file: vec![],
+        };
+
+        assert_eq!(
+            gh.annotation_command("warning", "Duplicate secret", "Line 2: DB\nLine 3: DB"),
+            "::warning title=Duplicate secret::Line 2: DB%0ALine 3: DB"
+        );
+        assert_eq!(
+            gh.annotation_command("error", "Failed: auth, 100%", "Denied"),
+            "::error title=Failed%3A auth%2C 100%25::Denied"
+        );
+    }
+
     #[test]
     fn test_multiline_secret_masking() {
         let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
@@ -161,6 +468,47 @@ BBBB_THIS_IS_LINE_THREE_SENSITIVE
         assert!(escaped.contains("%0A"), "Newlines should be escaped as %0A");
     }
 
+    #[test]
+    fn test_multiline_secret_masks_each_line() {
+        let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
+            env_file: vec![],
+            output_file: vec![],
+        };
+
+        let multiline_secret = "-----BEGIN TEST KEY-----
+AAAA_THIS_IS_LINE_TWO_SENSITIVE
+BBBB_THIS_IS_LINE_THREE_SENSITIVE
+    short
+-----END TEST KEY-----";
+
+        let commands = gh.mask_commands(multiline_secret);
+
+        assert_eq!(
+            commands,
+            vec![
+                format!("::add-mask::{}", gh.escape_secret(multiline_secret)),
+                "::add-mask::-----BEGIN TEST KEY-----".to_string(),
+                "::add-mask::AAAA_THIS_IS_LINE_TWO_SENSITIVE".to_string(),
+                "::add-mask::BBBB_THIS_IS_LINE_THREE_SENSITIVE".to_string(),
+                "::add-mask::-----END TEST KEY-----".to_string(),
+            ],
+            "Each line above the minimum length should be masked on its own"
+        );
+    }
+
+    #[test]
+    fn test_single_line_secret_is_masked_once() {
+        let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
+            env_file: vec![],
+            output_file: vec![],
+        };
+
+        assert_eq!(
+            gh.mask_commands("single-line-secret"),
+            vec!["::add-mask::single-line-secret".to_string()]
+        );
+    }
+
     #[test]
     fn test_escape_characters() {
         let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
@@ -195,6 +543,53 @@ BBBB_THIS_IS_LINE_THREE_SENSITIVE
         );
     }
 
+    #[test]
+    fn test_github_variable_names() {
+        let gh: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
+            env_file: vec![],
+            output_file: vec![],
+        };
+
+        assert!(gh.validate_variable_name("DB_PASSWORD").is_ok());
+        assert!(gh.validate_variable_name("_private2").is_ok());
+        assert!(gh.validate_variable_name("").is_err());
+        assert!(gh.validate_variable_name("2FA_CODE").is_err());
+        assert!(gh.validate_variable_name("DB PASSWORD").is_err());
+        assert!(gh.validate_variable_name("KEY=VALUE").is_err());
+        assert!(gh.validate_variable_name("GITHUB_TOKEN").is_err());
+        assert!(gh.validate_variable_name("github_token").is_err());
+
+        assert!(gh.validate_output_name("db-password").is_ok());
+        assert!(gh.validate_output_name("GITHUB_TOKEN").is_ok());
+        assert!(gh.validate_output_name("-password").is_err());
+        assert!(gh.validate_output_name("2FA_CODE").is_err());
+        assert!(gh.validate_output_name("DB PASSWORD").is_err());
+    }
+
+    #[test]
+    fn test_write_private_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("nested").join("secret.json");
+
+        let written = write_private_file(&path, "{\"key\": \"value\"}")?;
+        assert_eq!(written, path);
+        assert_eq!(std::fs::read_to_string(&path)?, "{\"key\": \"value\"}");
+
+        // Overwriting truncates the previous value
+        write_private_file(&path, "short")?;
+        assert_eq!(std::fs::read_to_string(&path)?, "short");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
     /// Asserts that name value pairs are written to the buffer in the correct format
     fn assert_github_output(buffer: &[u8], name: &str) -> Result<(String, uuid::Uuid)> {
         let delimiter_marker = "<<";
@@ -0,0 +1,209 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+
+use super::{ContinuousIntegration, get_var, validate_shell_variable_name, write_private_file};
+
+/// Prefixes of the variables set by Azure Pipelines itself, compared case-insensitively.
+const AZURE_RESERVED_PREFIXES: [&str; 5] = ["endpoint", "input", "secret", "path", "securefile"];
+
+/// Runs the action inside an Azure Pipelines job.
+///
+/// Azure Pipelines is driven entirely through `##vso[...]` logging commands written to stdout.
+pub struct AzurePipelinesRunner<W: Write> {
+    log: W,
+}
+
+impl<W: Write> AzurePipelinesRunner<W> {
+    fn issue_logging_command(&mut self, command: &str, value: &str) -> Result<()> {
+        let escaped_value = Self::escape_data(value);
+        writeln!(self.log, "##vso[{command}]{escaped_value}")?;
+        self.log.flush()?;
+        Ok(())
+    }
+
+    /// Escapes the message part of a logging command, matching `azure-pipelines-task-lib`.
+    fn escape_data(value: &str) -> String {
+        value
+            .replace('%', "%AZP25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    }
+
+    /// Escapes a `key=value` property of a logging command, which additionally may not contain
+    /// the `;` and `]` separators.
+    fn escape_property(value: &str) -> String {
+        Self::escape_data(value)
+            .replace(';', "%3B")
+            .replace(']', "%5D")
+    }
+}
+
+impl AzurePipelinesRunner<std::io::Stdout> {
+    pub fn new() -> Result<AzurePipelinesRunner<std::io::Stdout>> {
+        Ok(Self {
+            log: std::io::stdout(),
+        })
+    }
+}
+
+impl<W: Write> ContinuousIntegration for AzurePipelinesRunner<W> {
+    /// Inputs are read from `SM_`-prefixed pipeline variables, e.g. `SM_ACCESS_TOKEN`. Secret
+    /// variables must be mapped into the step's `env` explicitly.
+    fn get_input(&self, name: &str) -> Option<String> {
+        let upper_name = name.to_ascii_uppercase();
+        get_var(&format!("SM_{upper_name}"))
+    }
+
+    fn set_environment(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = Self::escape_property(name);
+        self.issue_logging_command(
+            &format!("task.setvariable variable={name};issecret=true"),
+            value,
+        )
+    }
+
+    fn set_output(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = Self::escape_property(name);
+        self.issue_logging_command(
+            &format!("task.setvariable variable={name};isOutput=true;issecret=true"),
+            value,
+        )
+    }
+
+    fn mask_value(&mut self, value: &str) {
+        let _ = self.issue_logging_command("task.setsecret", value);
+    }
+
+    /// Azure Pipelines reserves a few prefixes for the variables it sets itself.
+    fn validate_variable_name(&self, name: &str) -> Result<()> {
+        validate_shell_variable_name(name)?;
+
+        let lower_name = name.to_ascii_lowercase();
+        if let Some(prefix) = AZURE_RESERVED_PREFIXES
+            .iter()
+            .find(|prefix| lower_name.starts_with(*prefix))
+        {
+            bail!(
+                "'{name}' is not a valid variable name, as Azure Pipelines reserves the '{prefix}' prefix"
+            );
+        }
+        Ok(())
+    }
+
+    /// Relative paths are resolved against `AGENT_TEMPDIRECTORY`, which is emptied after every
+    /// job.
+    fn write_secret_file(&mut self, path: &Path, value: &str) -> Result<PathBuf> {
+        let temp_dir = get_var("AGENT_TEMPDIRECTORY")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        write_private_file(&temp_dir.join(path), value)
+    }
+
+    fn log(&mut self, message: &str) {
+        let _ = writeln!(self.log, "{message}");
+    }
+
+    fn start_group(&mut self, name: &str) {
+        let _ = writeln!(self.log, "##[group]{}", Self::escape_data(name));
+    }
+
+    fn end_group(&mut self) {
+        let _ = writeln!(self.log, "##[endgroup]");
+    }
+
+    /// Issues have no title of their own, so it leads the message instead.
+    fn warning(&mut self, title: &str, message: &str) {
+        let _ = self
+            .issue_logging_command("task.logissue type=warning", &format!("{title}: {message}"));
+    }
+
+    fn error(&mut self, title: &str, message: &str) {
+        let _ =
+            self.issue_logging_command("task.logissue type=error", &format!("{title}: {message}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn azure() -> AzurePipelinesRunner<Vec<u8>> {
+        AzurePipelinesRunner { log: vec![] }
+    }
+
+    #[test]
+    fn test_azure_logging_commands() -> Result<()> {
+        let mut azure = azure();
+
+        azure.set_environment("NAME1", "VALUE1")?;
+        azure.set_output("NAME2", "VALUE2")?;
+        azure.mask_value("VALUE3");
+
+        let written = String::from_utf8(azure.log)?;
+        assert_eq!(
+            written,
+            "##vso[task.setvariable variable=NAME1;issecret=true]VALUE1\n\
+             ##vso[task.setvariable variable=NAME2;isOutput=true;issecret=true]VALUE2\n\
+             ##vso[task.setsecret]VALUE3\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_azure_multiline_secret_escaping() -> Result<()> {
+        let mut azure = azure();
+
+        azure.set_environment("PEM", "-----BEGIN-----\r\n100%\n-----END-----")?;
+
+        let written = String::from_utf8(azure.log)?;
+        assert_eq!(
+            written,
+            "##vso[task.setvariable variable=PEM;issecret=true]-----BEGIN-----%0D%0A100%AZP25%0A-----END-----\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_azure_groups_and_issues() -> Result<()> {
+        let mut azure = azure();
+
+        azure.start_group("Retrieving secrets");
+        azure.log("Listing secrets...");
+        azure.warning("Duplicate secret", "Line 2\nLine 3");
+        azure.end_group();
+        azure.error("Authentication failed", "Denied");
+
+        let written = String::from_utf8(azure.log)?;
+        assert_eq!(
+            written,
+            "##[group]Retrieving secrets\n\
+             Listing secrets...\n\
+             ##vso[task.logissue type=warning]Duplicate secret: Line 2%0ALine 3\n\
+             ##[endgroup]\n\
+             ##vso[task.logissue type=error]Authentication failed: Denied\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_azure_property_escaping() {
+        assert_eq!(
+            AzurePipelinesRunner::<Vec<u8>>::escape_property("A;B]C"),
+            "A%3BB%5DC"
+        );
+    }
+
+    #[test]
+    fn test_azure_variable_names() {
+        let azure = azure();
+
+        assert!(azure.validate_variable_name("DB_PASSWORD").is_ok());
+        assert!(azure.validate_variable_name("SECRET_KEY").is_err());
+        assert!(azure.validate_variable_name("Path").is_err());
+        assert!(azure.validate_variable_name("1KEY").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+
+use super::{ContinuousIntegration, Layer};
+
+/// Keeps a copy of every variable set, so that all of them can be written out together once the
+/// secrets have been delivered.
+#[derive(Default)]
+pub struct Collecting {
+    variables: Vec<(String, String)>,
+}
+
+impl Collecting {
+    /// The variables set so far, in the order they were first set.
+    pub fn variables(&self) -> &[(String, String)] {
+        &self.variables
+    }
+}
+
+impl Layer for Collecting {
+    /// Every variable is set as an output, whether or not it goes to the environment too.
+    fn set_output(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        inner.set_output(name, value)?;
+
+        match self
+            .variables
+            .iter_mut()
+            .find(|(variable, _)| variable == name)
+        {
+            Some((_, existing)) => *existing = value.to_owned(),
+            None => self.variables.push((name.to_owned(), value.to_owned())),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::{Layered, LocalRunner, OutputFormat};
+
+    #[test]
+    fn test_collecting_runner_keeps_last_value() -> Result<()> {
+        let mut inner = LocalRunner::new(Vec::new(), OutputFormat::Env);
+
+        let mut runner = Layered::new(&mut inner, Collecting::default());
+        runner.set_output("FIRST", "one")?;
+        runner.set_output("SECOND", "two")?;
+        runner.set_output("FIRST", "three")?;
+
+        assert_eq!(
+            runner.layer().variables(),
+            [
+                ("FIRST".to_string(), "three".to_string()),
+                ("SECOND".to_string(), "two".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
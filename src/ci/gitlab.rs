@@ -0,0 +1,234 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+
+use super::{ContinuousIntegration, get_var, open_private};
+use crate::debug;
+
+const DEFAULT_DOTENV_PATH: &str = "sm-action.env";
+
+const YELLOW: &str = "\x1b[0;33m";
+const RED: &str = "\x1b[0;31m";
+const RESET: &str = "\x1b[0m";
+
+/// Runs the action inside a GitLab CI job.
+///
+/// A GitLab job cannot change its own environment, so secrets are written to a dotenv file
+/// instead. Publish it with `artifacts:reports:dotenv` to expose the secrets to downstream jobs.
+pub struct GitlabCiRunner<W: Write> {
+    dotenv_file: W,
+    /// The name of the open log section, if any.
+    section: Option<String>,
+}
+
+impl<W: Write> GitlabCiRunner<W> {
+    fn write_dotenv_line(&mut self, name: &str, value: &str) -> Result<()> {
+        // GitLab's dotenv report format is strictly one `KEY=VALUE` per line
+        if value.contains('\n') || value.contains('\r') {
+            bail!(
+                "The secret for '{name}' is multi-line, which GitLab dotenv reports do not support"
+            );
+        }
+
+        writeln!(self.dotenv_file, "{name}={value}")?;
+        self.dotenv_file.flush()?; // ensure the data is written to disk
+        Ok(())
+    }
+
+    /// Marks the start of a collapsible log section, which shows the header when collapsed.
+    fn section_start(header: &str, timestamp: u64) -> String {
+        let name = section_name(header);
+        format!("\x1b[0Ksection_start:{timestamp}:{name}[collapsed=true]\r\x1b[0K{header}")
+    }
+
+    fn section_end(header: &str, timestamp: u64) -> String {
+        let name = section_name(header);
+        format!("\x1b[0Ksection_end:{timestamp}:{name}\r\x1b[0K")
+    }
+}
+
+/// Section names may only contain letters, digits, `_`, `.` and `-`.
+fn section_name(header: &str) -> String {
+    header
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The dotenv report, which is only created once the first secret is written to it, so that
+/// e.g. the post step doesn't leave an empty one behind. Like secret files, it is only readable
+/// by the current user.
+pub struct DotenvReport {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl DotenvReport {
+    fn open(&mut self) -> std::io::Result<&mut File> {
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => open_private(&self.path, true)?,
+        };
+        Ok(self.file.insert(file))
+    }
+}
+
+impl Write for DotenvReport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.open()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GitlabCiRunner<DotenvReport> {
+    pub fn new() -> Result<GitlabCiRunner<DotenvReport>> {
+        let dotenv_path =
+            get_var("SM_DOTENV_PATH").unwrap_or_else(|| DEFAULT_DOTENV_PATH.to_string());
+
+        debug!("Writing to dotenv report: {dotenv_path}");
+
+        Ok(Self {
+            dotenv_file: DotenvReport {
+                path: PathBuf::from(dotenv_path),
+                file: None,
+            },
+            section: None,
+        })
+    }
+}
+
+impl<W: Write> ContinuousIntegration for GitlabCiRunner<W> {
+    /// Inputs are read from `SM_`-prefixed CI/CD variables, e.g. `SM_ACCESS_TOKEN`.
+    fn get_input(&self, name: &str) -> Option<String> {
+        let upper_name = name.to_ascii_uppercase();
+        get_var(&format!("SM_{upper_name}"))
+    }
+
+    /// The running job's environment cannot be modified; downstream jobs receive the secrets
+    /// through the dotenv report written by `set_output`.
+    fn set_environment(&mut self, name: &str, _value: &str) -> Result<()> {
+        debug!("Skipping environment for '{name}'; GitLab CI only supports dotenv reports");
+        Ok(())
+    }
+
+    fn set_output(&mut self, name: &str, value: &str) -> Result<()> {
+        self.write_dotenv_line(name, value)
+    }
+
+    /// GitLab only masks variables defined in the project settings, so this is a no-op.
+    fn mask_value(&mut self, _value: &str) {}
+
+    fn start_group(&mut self, name: &str) {
+        self.end_group();
+        println!("{}", Self::section_start(name, unix_timestamp()));
+        self.section = Some(name.to_string());
+    }
+
+    fn end_group(&mut self) {
+        if let Some(name) = self.section.take() {
+            println!("{}", Self::section_end(&name, unix_timestamp()));
+        }
+    }
+
+    /// GitLab has no annotations, so warnings and errors are highlighted in the job log instead.
+    fn warning(&mut self, title: &str, message: &str) {
+        println!("{YELLOW}Warning: {title}: {message}{RESET}");
+    }
+
+    fn error(&mut self, title: &str, message: &str) {
+        println!("{RED}Error: {title}: {message}{RESET}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitlab_dotenv_output() -> Result<()> {
+        let mut gitlab: GitlabCiRunner<Vec<u8>> = GitlabCiRunner {
+            dotenv_file: vec![],
+            section: None,
+        };
+
+        gitlab.set_output("NAME1", "VALUE1")?;
+        gitlab.set_output("NAME2", "VALUE=2")?;
+        gitlab.set_environment("NAME3", "VALUE3")?;
+
+        let written = String::from_utf8(gitlab.dotenv_file)?;
+        assert_eq!(written, "NAME1=VALUE1\nNAME2=VALUE=2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gitlab_rejects_multiline_values() {
+        let mut gitlab: GitlabCiRunner<Vec<u8>> = GitlabCiRunner {
+            dotenv_file: vec![],
+            section: None,
+        };
+
+        assert!(gitlab.set_output("NAME", "LINE1\nLINE2").is_err());
+        assert!(gitlab.set_output("NAME", "LINE1\r\nLINE2").is_err());
+        assert!(gitlab.dotenv_file.is_empty());
+    }
+
+    #[test]
+    fn test_dotenv_report_is_private_and_created_on_first_write() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+        let mut report = DotenvReport {
+            path: path.clone(),
+            file: None,
+        };
+
+        report.flush()?;
+        assert!(!path.exists());
+
+        writeln!(report, "NAME=VALUE")?;
+        assert_eq!(std::fs::read_to_string(&path)?, "NAME=VALUE\n");
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path)?.permissions())
+                & 0o777,
+            0o600
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gitlab_section_markers() {
+        assert_eq!(
+            GitlabCiRunner::<Vec<u8>>::section_start("Setting secrets", 42),
+            "\x1b[0Ksection_start:42:setting_secrets[collapsed=true]\r\x1b[0KSetting secrets"
+        );
+        assert_eq!(
+            GitlabCiRunner::<Vec<u8>>::section_end("Setting secrets", 43),
+            "\x1b[0Ksection_end:43:setting_secrets\r\x1b[0K"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::ContinuousIntegration;
+use crate::summary::SummaryEntry;
+
+/// Changes how some of a CI backend's methods behave, e.g. to refuse some variable names. Every
+/// hook forwards to the wrapped backend unless overridden, and the methods without a hook are
+/// always forwarded.
+pub trait Layer {
+    fn set_environment(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        inner.set_environment(name, value)
+    }
+
+    fn set_output(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        inner.set_output(name, value)
+    }
+
+    fn mask_value(&mut self, inner: &mut impl ContinuousIntegration, value: &str) {
+        inner.mask_value(value)
+    }
+
+    fn validate_variable_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        inner.validate_variable_name(name)
+    }
+
+    fn validate_output_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        inner.validate_output_name(name)
+    }
+
+    fn write_summary(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        entries: &[SummaryEntry],
+    ) -> Result<()> {
+        inner.write_summary(entries)
+    }
+}
+
+/// A CI backend with a layer on top, itself usable as a CI backend so that layers can be stacked.
+pub struct Layered<'a, T: ContinuousIntegration, L: Layer> {
+    inner: &'a mut T,
+    layer: L,
+}
+
+impl<'a, T: ContinuousIntegration, L: Layer> Layered<'a, T, L> {
+    pub fn new(inner: &'a mut T, layer: L) -> Self {
+        Self { inner, layer }
+    }
+
+    pub fn layer(&self) -> &L {
+        &self.layer
+    }
+}
+
+impl<T: ContinuousIntegration, L: Layer> ContinuousIntegration for Layered<'_, T, L> {
+    fn get_input(&self, name: &str) -> Option<String> {
+        self.inner.get_input(name)
+    }
+
+    fn set_environment(&mut self, name: &str, value: &str) -> Result<()> {
+        self.layer.set_environment(self.inner, name, value)
+    }
+
+    fn set_output(&mut self, name: &str, value: &str) -> Result<()> {
+        self.layer.set_output(self.inner, name, value)
+    }
+
+    fn mask_value(&mut self, value: &str) {
+        self.layer.mask_value(self.inner, value)
+    }
+
+    fn validate_variable_name(&self, name: &str) -> Result<()> {
+        self.layer.validate_variable_name(self.inner, name)
+    }
+
+    fn validate_output_name(&self, name: &str) -> Result<()> {
+        self.layer.validate_output_name(self.inner, name)
+    }
+
+    fn write_secret_file(&mut self, path: &Path, value: &str) -> Result<PathBuf> {
+        self.inner.write_secret_file(path, value)
+    }
+
+    fn file_manifest_path(&self) -> Option<PathBuf> {
+        self.inner.file_manifest_path()
+    }
+
+    fn log(&mut self, message: &str) {
+        self.inner.log(message)
+    }
+
+    fn start_group(&mut self, name: &str) {
+        self.inner.start_group(name)
+    }
+
+    fn end_group(&mut self) {
+        self.inner.end_group()
+    }
+
+    fn warning(&mut self, title: &str, message: &str) {
+        self.inner.warning(title, message)
+    }
+
+    fn error(&mut self, title: &str, message: &str) {
+        self.inner.error(title, message)
+    }
+
+    fn write_summary(&mut self, entries: &[SummaryEntry]) -> Result<()> {
+        self.layer.write_summary(self.inner, entries)
+    }
+}
@@ -0,0 +1,244 @@
+use std::{io::Write, str::FromStr};
+
+use anyhow::{Result, bail};
+
+use super::{ContinuousIntegration, get_var};
+use crate::{
+    debug, dotenv,
+    structured::{self, StructuredFormat},
+};
+
+/// How `LocalRunner` prints the retrieved secrets.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// `NAME=value` lines, as read by dotenv loaders.
+    #[default]
+    Env,
+    /// `export NAME='value'` lines, for `eval` in a POSIX shell.
+    Shell,
+    /// A single JSON object of names to values.
+    Json,
+    /// A single YAML mapping of names to values.
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input.trim().to_ascii_lowercase().as_ref() {
+            "env" => Ok(OutputFormat::Env),
+            "shell" => Ok(OutputFormat::Shell),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => bail!("Unknown format '{input}'. Expected 'env', 'shell', 'json' or 'yaml'"),
+        }
+    }
+}
+
+/// Inputs that stand in for each other, so that giving one as a command-line option ignores the
+/// environment variables of the others, e.g. `--access-token-file` overrides `SM_ACCESS_TOKEN`.
+const ALTERNATIVE_INPUTS: &[&[&str]] = &[
+    &["ACCESS_TOKEN", "ACCESS_TOKEN_FILE"],
+    &["BASE_URL", "API_URL", "IDENTITY_URL", "CLOUD_REGION"],
+];
+
+/// Runs the action from the command line, outside of any CI system.
+///
+/// Inputs come from the command-line options, falling back to `SM_`-prefixed environment
+/// variables, e.g. `SM_ACCESS_TOKEN`. The secrets are collected and printed once retrieved.
+pub struct LocalRunner {
+    inputs: Vec<(String, String)>,
+    format: OutputFormat,
+    outputs: Vec<(String, String)>,
+}
+
+impl LocalRunner {
+    /// Creates a runner with the given inputs, keyed by the names `Config` reads.
+    pub fn new(inputs: Vec<(String, String)>, format: OutputFormat) -> Self {
+        Self {
+            inputs,
+            format,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Prints the collected secrets in the requested format.
+    pub fn write_outputs<W: Write>(&self, out: &mut W) -> Result<()> {
+        match self.format {
+            OutputFormat::Env => {
+                for (name, value) in self.outputs.iter() {
+                    writeln!(out, "{}", dotenv::format_entry(name, value))?;
+                }
+            }
+            OutputFormat::Shell => {
+                for (name, value) in self.outputs.iter() {
+                    // Single quotes keep everything literal, except single quotes themselves
+                    writeln!(out, "export {name}='{}'", value.replace('\'', "'\\''"))?;
+                }
+            }
+            OutputFormat::Json => {
+                write!(
+                    out,
+                    "{}",
+                    structured::render(StructuredFormat::Json, &self.outputs)
+                )?;
+            }
+            OutputFormat::Yaml => {
+                write!(
+                    out,
+                    "{}",
+                    structured::render(StructuredFormat::Yaml, &self.outputs)
+                )?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Finds an input among the command-line options, or else with `environment` under its
+    /// `SM_` name.
+    fn find_input(
+        &self,
+        name: &str,
+        environment: impl Fn(&str) -> Option<String>,
+    ) -> Option<String> {
+        let upper_name = name.to_ascii_uppercase();
+        if let Some((_, value)) = self.inputs.iter().find(|(input, _)| *input == upper_name) {
+            return Some(value.clone());
+        }
+
+        let overridden = ALTERNATIVE_INPUTS
+            .iter()
+            .filter(|alternatives| alternatives.contains(&upper_name.as_str()))
+            .any(|alternatives| {
+                self.inputs
+                    .iter()
+                    .any(|(input, _)| alternatives.contains(&input.as_str()))
+            });
+        if overridden {
+            return None;
+        }
+        environment(&format!("SM_{upper_name}"))
+    }
+}
+
+impl ContinuousIntegration for LocalRunner {
+    fn get_input(&self, name: &str) -> Option<String> {
+        self.find_input(name, get_var)
+    }
+
+    /// A process cannot change the environment of the shell that started it; use
+    /// `--format shell` with `eval` instead.
+    fn set_environment(&mut self, name: &str, _value: &str) -> Result<()> {
+        debug!("Skipping environment for '{name}'; the secrets are printed instead");
+        Ok(())
+    }
+
+    fn set_output(&mut self, name: &str, value: &str) -> Result<()> {
+        match self.outputs.iter_mut().find(|(output, _)| output == name) {
+            Some((_, existing)) => *existing = value.to_owned(),
+            None => self.outputs.push((name.to_owned(), value.to_owned())),
+        }
+        Ok(())
+    }
+
+    /// There are no logs to mask values in locally, so this is a no-op.
+    fn mask_value(&mut self, _value: &str) {}
+
+    /// Progress goes to stderr, so that stdout only holds the secrets, e.g. for `eval`.
+    fn log(&mut self, message: &str) {
+        eprintln!("{message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner_with_outputs(format: OutputFormat) -> Result<LocalRunner> {
+        let mut runner = LocalRunner::new(Vec::new(), format);
+        runner.set_output("PLAIN", "value")?;
+        runner.set_output("QUOTED", "it's a \"secret\"\nline2")?;
+        Ok(runner)
+    }
+
+    #[test]
+    fn test_local_inputs() {
+        let runner = LocalRunner::new(
+            vec![("SECRETS".to_string(), "a > A\nb > B".to_string())],
+            OutputFormat::Env,
+        );
+
+        assert_eq!(
+            runner.get_input("secrets"),
+            Some("a > A\nb > B".to_string())
+        );
+    }
+
+    #[test]
+    fn test_options_override_alternative_environment_variables() {
+        let environment = |name: &str| {
+            ["SM_ACCESS_TOKEN", "SM_API_URL", "SM_SECRETS"]
+                .contains(&name)
+                .then(|| format!("{name} value"))
+        };
+        let runner = LocalRunner::new(
+            vec![
+                (
+                    "ACCESS_TOKEN_FILE".to_string(),
+                    "/run/secrets/token".to_string(),
+                ),
+                (
+                    "BASE_URL".to_string(),
+                    "https://vault.example.com".to_string(),
+                ),
+            ],
+            OutputFormat::Env,
+        );
+
+        assert_eq!(
+            runner.find_input("access_token_file", environment),
+            Some("/run/secrets/token".to_string())
+        );
+        assert_eq!(runner.find_input("access_token", environment), None);
+        assert_eq!(runner.find_input("api_url", environment), None);
+        assert_eq!(
+            runner.find_input("secrets", environment),
+            Some("SM_SECRETS value".to_string())
+        );
+    }
+
+    #[test]
+    fn test_write_env_and_shell_outputs() -> Result<()> {
+        let mut env = vec![];
+        runner_with_outputs(OutputFormat::Env)?.write_outputs(&mut env)?;
+        assert_eq!(
+            String::from_utf8(env)?,
+            "PLAIN=value\nQUOTED=\"it's a \\\"secret\\\"\\nline2\"\n"
+        );
+
+        let mut shell = vec![];
+        runner_with_outputs(OutputFormat::Shell)?.write_outputs(&mut shell)?;
+        assert_eq!(
+            String::from_utf8(shell)?,
+            "export PLAIN='value'\nexport QUOTED='it'\\''s a \"secret\"\nline2'\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_json_output() -> Result<()> {
+        let mut json = vec![];
+        runner_with_outputs(OutputFormat::Json)?.write_outputs(&mut json)?;
+
+        let parsed: serde_json::Value = serde_json::from_slice(&json)?;
+        assert_eq!(
+            parsed,
+            serde_json::json!({ "PLAIN": "value", "QUOTED": "it's a \"secret\"\nline2" })
+        );
+        assert!("json".parse::<OutputFormat>().is_ok());
+        assert!("xml".parse::<OutputFormat>().is_err());
+        Ok(())
+    }
+}
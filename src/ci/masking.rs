@@ -0,0 +1,165 @@
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use super::{ContinuousIntegration, Layer};
+
+/// Makes masking a value also mask the encodings commonly derived from it, which would
+/// otherwise leak the secret as soon as a script encodes it.
+pub struct Masking {
+    derivatives: bool,
+}
+
+impl Masking {
+    pub fn new(derivatives: bool) -> Self {
+        Self { derivatives }
+    }
+}
+
+/// Everything but the characters RFC 3986 leaves unreserved, as encoded by e.g.
+/// `encodeURIComponent` or Python's `urllib.parse.quote`.
+const URI_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// What HTML forms encode, as `application/x-www-form-urlencoded` with spaces as `+`.
+const FORM: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'*')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_');
+
+/// Returns the base64 (standard and URL-safe), percent-encoded, form-encoded and JSON-escaped
+/// forms of a value, leaving out any that are identical to the value itself.
+fn derived_encodings(value: &str) -> Vec<String> {
+    let json_escaped = serde_json::to_string(value).unwrap_or_default();
+
+    let candidates = [
+        STANDARD.encode(value),
+        URL_SAFE_NO_PAD.encode(value),
+        utf8_percent_encode(value, URI_COMPONENT).to_string(),
+        utf8_percent_encode(value, FORM)
+            .to_string()
+            .replace("%20", "+"),
+        // Strip the surrounding quotes
+        json_escaped[1..json_escaped.len() - 1].to_string(),
+    ];
+
+    let mut encodings: Vec<String> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if candidate != value && !encodings.contains(&candidate) {
+            encodings.push(candidate);
+        }
+    }
+    encodings
+}
+
+impl Layer for Masking {
+    fn mask_value(&mut self, inner: &mut impl ContinuousIntegration, value: &str) {
+        inner.mask_value(value);
+
+        if self.derivatives && !value.is_empty() {
+            for encoded in derived_encodings(value) {
+                inner.mask_value(&encoded);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::ci::Layered;
+
+    #[derive(Default)]
+    struct RecordingRunner {
+        masked_values: Vec<String>,
+    }
+
+    impl ContinuousIntegration for RecordingRunner {
+        fn get_input(&self, _name: &str) -> Option<String> {
+            None
+        }
+
+        fn set_environment(&mut self, _name: &str, _value: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_output(&mut self, _name: &str, _value: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn mask_value(&mut self, value: &str) {
+            self.masked_values.push(value.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_derived_encodings() {
+        let encodings = derived_encodings("p@ss/word?\"quoted\"\n");
+
+        assert_eq!(
+            encodings,
+            vec![
+                "cEBzcy93b3JkPyJxdW90ZWQiCg==".to_string(),
+                "cEBzcy93b3JkPyJxdW90ZWQiCg".to_string(),
+                "p%40ss%2Fword%3F%22quoted%22%0A".to_string(),
+                "p@ss/word?\\\"quoted\\\"\\n".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_derived_encodings_leave_unreserved_characters() {
+        let encodings = derived_encodings("ab-c@d");
+        assert!(encodings.contains(&"ab-c%40d".to_string()));
+
+        let encodings = derived_encodings("a b_c.d~");
+        assert!(encodings.contains(&"a%20b_c.d~".to_string()));
+        assert!(encodings.contains(&"a+b_c.d%7E".to_string()));
+    }
+
+    #[test]
+    fn test_derived_encodings_skip_identical_forms() {
+        // Alphanumeric values are unchanged by percent-encoding and JSON escaping
+        assert_eq!(
+            derived_encodings("token123"),
+            vec!["dG9rZW4xMjM=".to_string(), "dG9rZW4xMjM".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_masking_runner_masks_derivatives() {
+        let mut inner = RecordingRunner::default();
+
+        let mut runner = Layered::new(&mut inner, Masking::new(true));
+        runner.mask_value("token123");
+        runner.mask_value("");
+
+        assert_eq!(
+            inner.masked_values,
+            vec![
+                "token123".to_string(),
+                "dG9rZW4xMjM=".to_string(),
+                "dG9rZW4xMjM".to_string(),
+                "".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_masking_runner_without_derivatives() {
+        let mut inner = RecordingRunner::default();
+
+        let mut runner = Layered::new(&mut inner, Masking::new(false));
+        runner.mask_value("token123");
+
+        assert_eq!(inner.masked_values, vec!["token123".to_string()]);
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use super::{ContinuousIntegration, Layer};
+
+/// Without `set_env`, variables are only set as outputs, so their names only need to be valid
+/// output names, which some CI systems are less strict about.
+pub struct OutputOnly {
+    enabled: bool,
+}
+
+impl OutputOnly {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+}
+
+impl Layer for OutputOnly {
+    fn validate_variable_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        if self.enabled {
+            inner.validate_output_name(name)
+        } else {
+            inner.validate_variable_name(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::{GithubActionsRunner, Layered};
+
+    #[test]
+    fn test_output_only_names() {
+        let mut inner: GithubActionsRunner<Vec<u8>> = GithubActionsRunner {
+            env_file: vec![],
+            output_file: vec![],
+        };
+
+        let runner = Layered::new(&mut inner, OutputOnly::new(true));
+        assert!(runner.validate_variable_name("db-password").is_ok());
+
+        let runner = Layered::new(&mut inner, OutputOnly::new(false));
+        assert!(runner.validate_variable_name("db-password").is_err());
+    }
+}
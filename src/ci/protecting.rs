@@ -0,0 +1,132 @@
+use anyhow::{Result, bail};
+
+use super::{ContinuousIntegration, Layer};
+
+/// Variables that change how later commands, shells, interpreters or git behave, so that setting
+/// them from a secret could take over every later step of the job.
+const DEFAULT_PROTECTED_NAMES: &[&str] = &[
+    "PATH",
+    "HOME",
+    "SHELL",
+    "IFS",
+    "ENV",
+    "BASH_ENV",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PROMPT_COMMAND",
+    "PS4",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+    "LD_AUDIT",
+    "DYLD_INSERT_LIBRARIES",
+    "DYLD_LIBRARY_PATH",
+    "NODE_OPTIONS",
+    "NODE_PATH",
+    "PYTHONPATH",
+    "PYTHONSTARTUP",
+    "PERL5OPT",
+    "PERL5LIB",
+    "RUBYOPT",
+    "RUBYLIB",
+    "JAVA_TOOL_OPTIONS",
+    "_JAVA_OPTIONS",
+    "GIT_SSH",
+    "GIT_SSH_COMMAND",
+    "GIT_ASKPASS",
+    "GIT_EXEC_PATH",
+    "GIT_CONFIG_GLOBAL",
+    "GIT_CONFIG_SYSTEM",
+    "SSH_ASKPASS",
+];
+
+/// Refuses protected variable names, which would otherwise let a secret change the behavior of
+/// every later step.
+pub struct Protecting {
+    protected_names: Vec<String>,
+}
+
+impl Protecting {
+    /// Protects the built-in names plus `extra`, except for the names in `allowed`.
+    pub fn new(extra: &[String], allowed: &[String]) -> Self {
+        let protected_names = DEFAULT_PROTECTED_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(extra.iter().cloned())
+            .filter(|name| {
+                !allowed
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            })
+            .collect();
+
+        Self { protected_names }
+    }
+
+    /// Names are compared case-insensitively, as they are on Windows runners.
+    fn check_name(&self, name: &str) -> Result<()> {
+        if self
+            .protected_names
+            .iter()
+            .any(|protected| protected.eq_ignore_ascii_case(name))
+        {
+            bail!(
+                "'{name}' is a protected variable name, as setting it would change the behavior of later steps. If this is intended, add it to 'allow_protected_names'"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Layer for Protecting {
+    fn set_environment(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        self.check_name(name)?;
+        inner.set_environment(name, value)
+    }
+
+    /// Rejecting protected names up front fails the step before any secret is delivered.
+    fn validate_variable_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        inner.validate_variable_name(name)?;
+        self.check_name(name)
+    }
+
+    /// Outputs may still end up in an environment, e.g. that of the command run by `exec`.
+    fn validate_output_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        inner.validate_output_name(name)?;
+        self.check_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::{Layered, LocalRunner, OutputFormat};
+
+    #[test]
+    fn test_protected_names() {
+        let mut inner = LocalRunner::new(Vec::new(), OutputFormat::Env);
+        let mut runner = Layered::new(
+            &mut inner,
+            Protecting::new(
+                &["DEPLOY_HOOK".to_string()],
+                &["java_tool_options".to_string()],
+            ),
+        );
+
+        assert!(runner.validate_variable_name("DB_PASSWORD").is_ok());
+        assert!(runner.validate_variable_name("LD_PRELOAD").is_err());
+        assert!(runner.validate_variable_name("Path").is_err());
+        assert!(runner.validate_variable_name("DEPLOY_HOOK").is_err());
+        assert!(runner.validate_variable_name("JAVA_TOOL_OPTIONS").is_ok());
+
+        let error = runner
+            .set_environment("BASH_ENV", "/tmp/evil.sh")
+            .unwrap_err();
+        assert!(error.to_string().contains("protected"));
+        assert!(runner.set_environment("DB_PASSWORD", "secret").is_ok());
+    }
+}
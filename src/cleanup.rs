@@ -0,0 +1,238 @@
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{ci::ContinuousIntegration, debug};
+
+/// The result of cleaning up a single file listed in the manifest.
+#[derive(Debug, PartialEq)]
+pub enum CleanupOutcome {
+    Removed(PathBuf),
+    /// The file had already been deleted by something else.
+    NotFound(PathBuf),
+    /// The file could not be removed; the error describes why.
+    Failed(PathBuf, String),
+}
+
+/// Records a file written by the action so the post step can remove it again.
+pub fn record_file(manifest_path: &Path, file_path: &Path) -> Result<()> {
+    let mut manifest = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(manifest_path)?;
+    writeln!(manifest, "{}", file_path.display())?;
+    manifest.flush()?;
+    Ok(())
+}
+
+/// Overwrites and deletes every file listed in the manifest, then deletes the manifest itself.
+pub fn clean_up(manifest_path: &Path) -> Result<Vec<CleanupOutcome>> {
+    let manifest = match std::fs::File::open(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut outcomes = Vec::new();
+    let mut seen = HashSet::new();
+    for line in BufReader::new(manifest).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // The same file may have been written by several runs of the action in one job
+        let path = PathBuf::from(line);
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+
+        // One file failing to be removed mustn't leave the others behind
+        let outcome = match remove_file(&path) {
+            Ok(true) => CleanupOutcome::Removed(path),
+            Ok(false) => CleanupOutcome::NotFound(path),
+            Err(e) => CleanupOutcome::Failed(path, e.to_string()),
+        };
+        outcomes.push(outcome);
+    }
+
+    std::fs::remove_file(manifest_path)?;
+    Ok(outcomes)
+}
+
+/// Removes a recorded file, returning whether it still existed. A symlink is only unlinked, as
+/// whatever it points to wasn't written by the action.
+fn remove_file(path: &Path) -> Result<bool> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    if metadata.is_symlink() {
+        std::fs::remove_file(path)?;
+    } else if metadata.is_file() {
+        scrub_file(path)?;
+    } else {
+        anyhow::bail!("Not a regular file");
+    }
+    Ok(true)
+}
+
+/// Overwrites the contents of a file with zeros before deleting it, so the secret doesn't
+/// linger in the freed disk blocks.
+fn scrub_file(path: &Path) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    // Refuse to write through a symlink swapped in since the file was checked
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+
+    let mut file = options.open(path)?;
+    let length = file.metadata()?.len();
+    let zeros = [0u8; 4096];
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Entry point for the `--post` mode, which runs after the job has finished.
+pub fn run_post<T: ContinuousIntegration>(ci: &mut T) -> Result<()> {
+    let Some(manifest_path) = ci.file_manifest_path() else {
+        debug!("No file manifest for this CI system; nothing to clean up");
+        return Ok(());
+    };
+
+    let outcomes = clean_up(&manifest_path)?;
+    if outcomes.is_empty() {
+        ci.log("No secret files to clean up.");
+        return Ok(());
+    }
+
+    let mut failures = Vec::new();
+    for outcome in outcomes.iter() {
+        match outcome {
+            CleanupOutcome::Removed(path) => ci.log(&format!("Removed {}", path.display())),
+            CleanupOutcome::NotFound(path) => {
+                ci.log(&format!("Already removed {}", path.display()))
+            }
+            CleanupOutcome::Failed(path, error) => {
+                failures.push(format!("{}: {error}", path.display()))
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "Failed to remove {} secret file(s):\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+
+    ci.log("Completed cleaning up secret files.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_up_removes_recorded_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        let manifest_path = dir.join("manifest");
+        let first = dir.join("first.json");
+        let second = dir.join("second.pem");
+        let missing = dir.join("missing.txt");
+
+        std::fs::write(&first, "secret one")?;
+        std::fs::write(&second, "secret two")?;
+
+        record_file(&manifest_path, &first)?;
+        record_file(&manifest_path, &second)?;
+        record_file(&manifest_path, &missing)?;
+        record_file(&manifest_path, &first)?;
+
+        let outcomes = clean_up(&manifest_path)?;
+
+        assert_eq!(
+            outcomes,
+            vec![
+                CleanupOutcome::Removed(first.clone()),
+                CleanupOutcome::Removed(second.clone()),
+                CleanupOutcome::NotFound(missing),
+            ]
+        );
+        assert!(!first.exists());
+        assert!(!second.exists());
+        assert!(!manifest_path.exists());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clean_up_unlinks_symlinks_and_continues_after_failures() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        let manifest_path = dir.join("manifest");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        let directory = dir.join("directory");
+        let file = dir.join("file.txt");
+
+        std::fs::write(&target, "not a secret")?;
+        std::os::unix::fs::symlink(&target, &link)?;
+        std::fs::create_dir(&directory)?;
+        std::fs::write(&file, "secret")?;
+
+        record_file(&manifest_path, &link)?;
+        record_file(&manifest_path, &directory)?;
+        record_file(&manifest_path, &file)?;
+
+        let outcomes = clean_up(&manifest_path)?;
+
+        assert_eq!(
+            outcomes,
+            vec![
+                CleanupOutcome::Removed(link.clone()),
+                CleanupOutcome::Failed(directory.clone(), "Not a regular file".to_string()),
+                CleanupOutcome::Removed(file.clone()),
+            ]
+        );
+        assert!(!link.exists());
+        assert!(!file.exists());
+        assert_eq!(std::fs::read_to_string(&target)?, "not a secret");
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_clean_up_without_manifest() -> Result<()> {
+        let manifest_path =
+            std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+
+        assert!(clean_up(&manifest_path)?.is_empty());
+        Ok(())
+    }
+}
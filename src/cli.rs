@@ -0,0 +1,203 @@
+use anyhow::{Result, bail};
+
+use crate::ci::OutputFormat;
+
+pub const USAGE: &str = "\
+Retrieves secrets from Bitwarden Secrets Manager.
+
+Usage:
+  sm-action [OPTIONS]
+  sm-action [OPTIONS] exec -- <COMMAND> [ARGS...]
+
+Inside a CI system the inputs are read from the job, e.g. the action's `with:` inputs on GitHub
+Actions. Outside of one, or when passing any of the options below, it runs locally instead,
+printing the secrets to stdout.
+
+Options:
+  --access-token-file <PATH>  Read the access token from a file, or from stdin if '-'
+                              (defaults to the SM_ACCESS_TOKEN environment variable)
+  --secret <SECRET>           A secret to retrieve, e.g. 'UUID > NAME'; can be repeated
+  --server-url <URL>          The URL of a self-hosted server
+  --format <FORMAT>           How to print the secrets: env (default), shell, json
+                              or yaml
+  --version                   Print the version
+  -h, --help                  Print this help
+
+Commands:
+  exec -- <COMMAND>           Run a command with the secrets in its environment only
+";
+
+const EXEC_SUBCOMMAND: &str = "exec";
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Retrieve the secrets and deliver them through the CI system.
+    Run,
+    /// Run a command with the secrets in its environment only.
+    Exec(Vec<String>),
+    /// Clean up after the job, as the action's post step.
+    Post,
+    Version,
+    Help,
+}
+
+/// The parsed command line.
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    /// Inputs given as options, keyed by the names `Config` reads.
+    pub inputs: Vec<(String, String)>,
+    pub format: Option<OutputFormat>,
+}
+
+impl Cli {
+    /// Parses the arguments, excluding the program name.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut command = Command::Run;
+        let mut inputs = Vec::new();
+        let mut secrets: Vec<String> = Vec::new();
+        let mut format = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // Options take their value either as `--name value` or as `--name=value`
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| anyhow::anyhow!("Missing value for '{name}'"))
+            };
+
+            match name {
+                "--access-token-file" => inputs.push(("ACCESS_TOKEN_FILE".to_string(), value()?)),
+                "--secret" => secrets.push(value()?),
+                "--server-url" => inputs.push(("BASE_URL".to_string(), value()?)),
+                "--format" => format = Some(value()?.parse()?),
+                "--version" => command = Command::Version,
+                "--post" => command = Command::Post,
+                "-h" | "--help" => command = Command::Help,
+                EXEC_SUBCOMMAND => {
+                    // Everything after `exec` belongs to the command, so none of it is read as
+                    // our own options
+                    let rest: Vec<String> = args.by_ref().cloned().collect();
+                    let rest = match rest.split_first() {
+                        Some((first, tail)) if first == "--" => tail.to_vec(),
+                        _ => rest,
+                    };
+                    if rest.is_empty() {
+                        bail!("No command given. Usage: sm-action exec -- <COMMAND> [ARGS...]");
+                    }
+                    command = Command::Exec(rest);
+                }
+                _ => bail!("Unknown argument '{arg}'. Run 'sm-action --help' for usage."),
+            }
+        }
+
+        if !secrets.is_empty() {
+            inputs.push(("SECRETS".to_string(), secrets.join("\n")));
+        }
+
+        Ok(Self {
+            command,
+            inputs,
+            format,
+        })
+    }
+
+    /// Whether to run locally rather than inside a CI system, which any local option implies.
+    pub fn is_local(&self) -> bool {
+        !self.inputs.is_empty() || self.format.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Cli::parse(&args)
+    }
+
+    #[test]
+    fn test_parse_ci_invocations() -> Result<()> {
+        assert_eq!(parse(&[])?.command, Command::Run);
+        assert_eq!(parse(&["--post"])?.command, Command::Post);
+        assert_eq!(parse(&["--version"])?.command, Command::Version);
+        assert!(!parse(&["--post"])?.is_local());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_local_options() -> Result<()> {
+        let cli = parse(&[
+            "--access-token-file",
+            "/run/secrets/token",
+            "--secret",
+            "00000000-0000-0000-0000-000000000000 > FIRST",
+            "--secret=key:API_KEY > SECOND",
+            "--server-url=https://vault.example.com",
+            "--format",
+            "json",
+        ])?;
+
+        assert_eq!(
+            cli,
+            Cli {
+                command: Command::Run,
+                inputs: vec![
+                    (
+                        "ACCESS_TOKEN_FILE".to_string(),
+                        "/run/secrets/token".to_string()
+                    ),
+                    (
+                        "BASE_URL".to_string(),
+                        "https://vault.example.com".to_string()
+                    ),
+                    (
+                        "SECRETS".to_string(),
+                        "00000000-0000-0000-0000-000000000000 > FIRST\nkey:API_KEY > SECOND"
+                            .to_string()
+                    ),
+                ],
+                format: Some(OutputFormat::Json),
+            }
+        );
+        assert!(cli.is_local());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_exec() -> Result<()> {
+        assert_eq!(
+            parse(&[
+                "--secret",
+                "a > A",
+                "exec",
+                "--",
+                "./deploy.sh",
+                "--version"
+            ])?
+            .command,
+            Command::Exec(vec!["./deploy.sh".to_string(), "--version".to_string()])
+        );
+        assert_eq!(
+            parse(&["exec", "env"])?.command,
+            Command::Exec(vec!["env".to_string()])
+        );
+        assert!(parse(&["exec", "--"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--secret"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+    }
+}
@@ -1,6 +1,14 @@
+use std::{io::Read, time::Duration};
+
 use anyhow::{Result, bail};
 
-use crate::ci::ContinuousIntegration;
+use crate::{
+    ci::ContinuousIntegration,
+    error::{ErrorKind, ResultExt},
+    retry::DEFAULT_RETRIES,
+    structured::StructuredFormat,
+    timeout::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT},
+};
 
 /// Prints a debug message to the GitHub Actions log if `RUNNER_DEBUG` or `ACTIONS_RUNNER_DEBUG` are set.
 #[macro_export]
@@ -45,6 +53,23 @@ pub struct Config {
     pub api_url: Option<String>,
     pub identity_url: Option<String>,
     pub set_env: bool,
+    pub allow_missing: bool,
+    pub mask_derivatives: bool,
+    /// Also write every variable to this dotenv file.
+    pub dotenv_file: Option<String>,
+    /// Also provide every variable as a single document, in `output_file` or as one output.
+    pub output_format: Option<StructuredFormat>,
+    pub output_file: Option<String>,
+    /// Extra variable names to refuse, on top of the built-in protected names.
+    pub protected_names: Vec<String>,
+    /// Protected names to allow anyway.
+    pub allowed_protected_names: Vec<String>,
+    /// How many times to retry a request that failed for a transient reason.
+    pub retries: u32,
+    /// The deadline for authenticating and retrieving the secrets, if any.
+    pub timeout: Option<Duration>,
+    /// How long connecting to the servers may take before it is retried, if limited.
+    pub connect_timeout: Option<Duration>,
 }
 
 impl Config {
@@ -53,17 +78,24 @@ impl Config {
         let cloud_region =
             EnvironmentType::from_str(&ci.get_input("CLOUD_REGION").unwrap_or_default());
 
-        let access_token = ci
-            .get_input("ACCESS_TOKEN")
-            .ok_or_else(|| anyhow::anyhow!("Access token is required"))?;
+        let access_token = match (
+            ci.get_input("ACCESS_TOKEN"),
+            ci.get_input("ACCESS_TOKEN_FILE"),
+        ) {
+            (Some(_), Some(_)) => {
+                bail!("Only one of access_token and access_token_file may be provided")
+            }
+            (Some(access_token), None) => access_token,
+            (None, Some(path)) => read_access_token_file(&path)?,
+            (None, None) => bail!("Access token is required"),
+        };
 
         let secrets = ci
             .get_input("SECRETS")
             .ok_or_else(|| anyhow::anyhow!("Secrets are required"))?
             .lines()
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .map(String::from)
+            // Blank lines are kept so errors can refer to the right line number
+            .map(|line| line.trim().to_string())
             .collect();
 
         let base_url = ci.get_input("BASE_URL");
@@ -80,13 +112,54 @@ impl Config {
             base_url.as_deref(),
             api_url.as_deref(),
             identity_url.as_deref(),
-        )?;
+        )
+        .kind(ErrorKind::InvalidUrl)?;
 
         let set_env = ci
             .get_input("SET_ENV")
             .is_some_and(|val| !val.eq_ignore_ascii_case("false"));
         debug!("set_env: {set_env}");
 
+        let allow_missing = ci
+            .get_input("ALLOW_MISSING")
+            .is_some_and(|val| val.eq_ignore_ascii_case("true"));
+        debug!("allow_missing: {allow_missing}");
+
+        let mask_derivatives = ci
+            .get_input("MASK_DERIVATIVES")
+            .is_none_or(|val| !val.eq_ignore_ascii_case("false"));
+        debug!("mask_derivatives: {mask_derivatives}");
+
+        let dotenv_file = ci.get_input("DOTENV_FILE");
+        debug!("dotenv_file: {dotenv_file:?}");
+
+        let output_file = ci.get_input("OUTPUT_FILE");
+        let output_format = match ci.get_input("OUTPUT_FORMAT") {
+            Some(format) => Some(format.parse()?),
+            // A file is of no use without a document to write to it
+            None => output_file.as_ref().map(|_| StructuredFormat::Json),
+        };
+        debug!("output_format: {output_format:?}");
+        debug!("output_file: {output_file:?}");
+
+        let protected_names = parse_name_list(ci.get_input("PROTECTED_NAMES"));
+        let allowed_protected_names = parse_name_list(ci.get_input("ALLOW_PROTECTED_NAMES"));
+        debug!("protected_names: {protected_names:?}");
+        debug!("allowed_protected_names: {allowed_protected_names:?}");
+
+        let retries = match ci.get_input("RETRIES") {
+            Some(retries) => retries.trim().parse().map_err(|_| {
+                anyhow::anyhow!("retries must be a whole number, but was '{retries}'")
+            })?,
+            None => DEFAULT_RETRIES,
+        };
+        debug!("retries: {retries}");
+
+        let timeout = parse_timeout(ci, "TIMEOUT", DEFAULT_TIMEOUT)?;
+        let connect_timeout = parse_timeout(ci, "CONNECT_TIMEOUT", DEFAULT_CONNECT_TIMEOUT)?;
+        debug!("timeout: {timeout:?}");
+        debug!("connect_timeout: {connect_timeout:?}");
+
         Ok(Self {
             access_token,
             secrets,
@@ -95,10 +168,81 @@ impl Config {
             api_url,
             identity_url,
             set_env,
+            allow_missing,
+            mask_derivatives,
+            dotenv_file,
+            output_format,
+            output_file,
+            protected_names,
+            allowed_protected_names,
+            retries,
+            timeout,
+            connect_timeout,
         })
     }
 }
 
+/// Reads a timeout in whole seconds, where `0` means no limit.
+fn parse_timeout<T: ContinuousIntegration>(
+    ci: &T,
+    name: &str,
+    default: Duration,
+) -> Result<Option<Duration>> {
+    let Some(input) = ci.get_input(name) else {
+        return Ok(Some(default));
+    };
+    let seconds: u64 = input.trim().parse().map_err(|_| {
+        anyhow::anyhow!(
+            "{} must be a whole number of seconds, but was '{input}'",
+            name.to_ascii_lowercase()
+        )
+    })?;
+    Ok((seconds > 0).then(|| Duration::from_secs(seconds)))
+}
+
+/// Splits a list of names separated by commas or newlines.
+fn parse_name_list(input: Option<String>) -> Vec<String> {
+    input
+        .unwrap_or_default()
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads the access token from a file, or from stdin if the path is `-`, so that it never has to
+/// be placed in the environment. Trailing newlines are trimmed.
+fn read_access_token_file(path: &str) -> Result<String> {
+    if path == "-" {
+        debug!("Reading access token from stdin");
+        return read_access_token(std::io::stdin().lock(), "stdin");
+    }
+
+    debug!("Reading access token from {path}");
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("The access token file '{path}' was not found")
+        }
+        Err(e) => bail!("The access token file '{path}' could not be read: {e}"),
+    };
+    read_access_token(file, &format!("access token file '{path}'"))
+}
+
+fn read_access_token<R: Read>(mut reader: R, source: &str) -> Result<String> {
+    let mut contents = String::new();
+    reader
+        .read_to_string(&mut contents)
+        .map_err(|e| anyhow::anyhow!("The {source} could not be read: {e}"))?;
+
+    let access_token = contents.trim_end_matches(['\r', '\n']);
+    if access_token.trim().is_empty() {
+        bail!("The {source} is empty");
+    }
+    Ok(access_token.to_string())
+}
+
 fn validate_urls(
     base_url: Option<&str>,
     api_url: Option<&str>,
@@ -199,17 +343,36 @@ pub fn infer_urls(config: &Config) -> Result<(String, String)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ci::{LocalRunner, OutputFormat};
 
-    #[test]
-    fn test_infer_urls_with_both_api_and_identity() {
-        let config = Config {
+    fn test_config() -> Config {
+        Config {
             access_token: "fake_access_token".to_string(),
             secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region: EnvironmentType::Other,
             base_url: None,
+            api_url: None,
+            identity_url: None,
+            set_env: true,
+            allow_missing: false,
+            mask_derivatives: true,
+            dotenv_file: None,
+            output_format: None,
+            output_file: None,
+            protected_names: Vec::new(),
+            allowed_protected_names: Vec::new(),
+            retries: DEFAULT_RETRIES,
+            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+        }
+    }
+
+    #[test]
+    fn test_infer_urls_with_both_api_and_identity() {
+        let config = Config {
             api_url: Some("https://api.example.com".to_string()),
             identity_url: Some("https://identity.example.com".to_string()),
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -219,15 +382,7 @@ mod tests {
 
     #[test]
     fn test_infer_urls_defaults_to_us_cloud_region() {
-        let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
-            cloud_region: EnvironmentType::Other,
-            base_url: None,
-            api_url: None,
-            identity_url: None,
-            set_env: true,
-        };
+        let config = test_config();
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
         assert_eq!(api_url, US_DEFAULT_API_URL);
@@ -237,13 +392,8 @@ mod tests {
     #[test]
     fn test_infer_urls_with_base_url_only() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
-            cloud_region: EnvironmentType::Other,
             base_url: Some("https://example.com".to_string()),
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -254,13 +404,9 @@ mod tests {
     #[test]
     fn test_infer_urls_with_api_and_identity() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
-            cloud_region: EnvironmentType::Other,
-            base_url: None,
             api_url: Some("https://api.example.com".to_string()),
             identity_url: Some("https://identity.example.com".to_string()),
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -271,13 +417,8 @@ mod tests {
     #[test]
     fn test_infer_urls_with_eu_region() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region: EnvironmentType::Eu,
-            base_url: None,
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
         let (api_url, identity_url) = infer_urls(&config).unwrap();
         assert_eq!(api_url, EU_DEFAULT_API_URL);
@@ -287,13 +428,8 @@ mod tests {
     #[test]
     fn test_infer_urls_with_us_region() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region: EnvironmentType::Us,
-            base_url: None,
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -305,13 +441,8 @@ mod tests {
     fn test_ensure_case_insensitivity_for_eu_region() {
         let cloud_region = EnvironmentType::from_str("eU");
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region,
-            base_url: None,
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
         let (api_url, identity_url) = infer_urls(&config).unwrap();
         assert_eq!(api_url, EU_DEFAULT_API_URL);
@@ -322,13 +453,8 @@ mod tests {
     fn test_ensure_case_insensitivity_for_us_region() {
         let cloud_region = EnvironmentType::from_str("uS");
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region,
-            base_url: None,
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -339,13 +465,9 @@ mod tests {
     #[test]
     fn test_infer_urls_with_cloud_region_and_base_url_should_use_cloud_region() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region: EnvironmentType::Eu,
             base_url: Some("https://example.com".to_string()),
-            api_url: None,
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -356,13 +478,10 @@ mod tests {
     #[test]
     fn test_infer_urls_with_cloud_region_and_api_identity_should_use_cloud_region() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
             cloud_region: EnvironmentType::Eu,
-            base_url: None,
             api_url: Some("https://api.example.com".to_string()),
             identity_url: Some("https://identity.example.com".to_string()),
-            set_env: true,
+            ..test_config()
         };
 
         let (api_url, identity_url) = infer_urls(&config).unwrap();
@@ -373,13 +492,8 @@ mod tests {
     #[test]
     fn test_infer_urls_with_only_api_should_fail() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
-            cloud_region: EnvironmentType::Other,
-            base_url: None,
             api_url: Some("https://api.example.com".to_string()),
-            identity_url: None,
-            set_env: true,
+            ..test_config()
         };
 
         let result = infer_urls(&config);
@@ -393,13 +507,8 @@ mod tests {
     #[test]
     fn test_infer_urls_with_only_identity_should_fail() {
         let config = Config {
-            access_token: "fake_access_token".to_string(),
-            secrets: vec!["de66de56-0b1f-42ff-8033-8b7866416520 > SECRET_NAME".to_string()],
-            cloud_region: EnvironmentType::Other,
-            base_url: None,
-            api_url: None,
             identity_url: Some("https://identity.example.com".to_string()),
-            set_env: true,
+            ..test_config()
         };
 
         let result = infer_urls(&config);
@@ -410,6 +519,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_access_token_trims_trailing_newlines() -> Result<()> {
+        assert_eq!(
+            read_access_token("0.token:key\r\n".as_bytes(), "stdin")?,
+            "0.token:key"
+        );
+        assert_eq!(
+            read_access_token("0.token:key".as_bytes(), "stdin")?,
+            "0.token:key"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_access_token_file_errors() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("sm-action-test-{}", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let not_found = read_access_token_file(path).unwrap_err().to_string();
+        assert!(not_found.contains("was not found"), "{not_found}");
+
+        std::fs::write(path, "\n")?;
+        let empty = read_access_token_file(path).unwrap_err().to_string();
+        assert!(empty.contains("is empty"), "{empty}");
+
+        std::fs::write(path, "0.token:key\n")?;
+        assert_eq!(read_access_token_file(path)?, "0.token:key");
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_name_list() {
+        assert_eq!(
+            parse_name_list(Some("DEPLOY_HOOK, PAGER\nEDITOR\n".to_string())),
+            vec!["DEPLOY_HOOK", "PAGER", "EDITOR"]
+        );
+        assert!(parse_name_list(None).is_empty());
+    }
+
+    #[test]
+    fn test_parse_timeout() -> Result<()> {
+        let ci = LocalRunner::new(
+            vec![
+                ("TIMEOUT".to_string(), "45".to_string()),
+                ("CONNECT_TIMEOUT".to_string(), "0".to_string()),
+                ("INVALID_TIMEOUT".to_string(), "1m".to_string()),
+            ],
+            OutputFormat::Env,
+        );
+
+        assert_eq!(
+            parse_timeout(&ci, "TIMEOUT", DEFAULT_TIMEOUT)?,
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(
+            parse_timeout(&ci, "CONNECT_TIMEOUT", DEFAULT_CONNECT_TIMEOUT)?,
+            None
+        );
+        assert_eq!(
+            parse_timeout(&ci, "MISSING_TIMEOUT", DEFAULT_TIMEOUT)?,
+            Some(DEFAULT_TIMEOUT)
+        );
+        assert!(parse_timeout(&ci, "INVALID_TIMEOUT", DEFAULT_TIMEOUT).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_ensure_environment_enum_correctly_matches_input() {
         let eu_cloud_region = EnvironmentType::from_str("eu");
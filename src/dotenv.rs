@@ -0,0 +1,206 @@
+use anyhow::{Result, bail};
+
+/// Parses the contents of a dotenv file into its entries, in the order they appear.
+///
+/// Supports `#` comments, an optional `export` prefix, unquoted values, single-quoted values
+/// which are taken literally, and double-quoted values which may contain escapes such as `\n`
+/// and may span multiple lines.
+pub fn parse(content: &str) -> Result<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            bail!(
+                "Line {} of the dotenv secret is not a KEY=value pair",
+                index + 1
+            );
+        };
+
+        let key = key.trim();
+        if key.is_empty() {
+            bail!("Line {} of the dotenv secret has an empty key", index + 1);
+        }
+
+        let value = value.trim_start();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            let Some((literal, _)) = quoted.split_once('\'') else {
+                bail!(
+                    "Line {} of the dotenv secret has an unclosed quote",
+                    index + 1
+                );
+            };
+            literal.to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            // Keep consuming lines until the closing quote
+            let mut quoted = quoted.to_string();
+            loop {
+                if let Some(value) = unescape_double_quoted(&quoted) {
+                    break value;
+                }
+                let Some((_, next_line)) = lines.next() else {
+                    bail!(
+                        "Line {} of the dotenv secret has an unclosed quote",
+                        index + 1
+                    );
+                };
+                quoted.push('\n');
+                quoted.push_str(next_line);
+            }
+        } else {
+            // Unquoted values end at an inline comment
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.trim_end().to_string(),
+            }
+        };
+
+        entries.push((key.to_string(), value));
+    }
+
+    Ok(entries)
+}
+
+/// Formats an entry as a `KEY=value` line that `parse` reads back unchanged. Values that need
+/// quoting are single-quoted, so that tools such as Docker Compose don't expand `$` in them, and
+/// only double-quoted and escaped if they contain a single quote or a line break.
+pub fn format_entry(key: &str, value: &str) -> String {
+    let is_plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-./:@+,=".contains(c));
+    if is_plain {
+        return format!("{key}={value}");
+    }
+    if !value.contains(['\'', '\n', '\r']) {
+        return format!("{key}='{value}'");
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    format!("{key}={quoted}")
+}
+
+/// Unescapes a double-quoted value up to its closing quote. Returns `None` if the closing quote
+/// hasn't been reached yet.
+fn unescape_double_quoted(quoted: &str) -> Option<String> {
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some(escaped) => value.push(escaped),
+                None => value.push('\\'),
+            },
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_dotenv() -> Result<()> {
+        let content = r#"
+# Database settings
+DB_HOST=db.example.com
+export DB_PORT = 5432
+DB_USER='admin # not a comment'
+DB_PASSWORD="p@ss \"word\"" # a comment
+EMPTY=
+URL=https://example.com/#anchor # trailing comment
+"#;
+
+        assert_eq!(
+            parse(content)?,
+            vec![
+                entry("DB_HOST", "db.example.com"),
+                entry("DB_PORT", "5432"),
+                entry("DB_USER", "admin # not a comment"),
+                entry("DB_PASSWORD", "p@ss \"word\""),
+                entry("EMPTY", ""),
+                entry("URL", "https://example.com/#anchor"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dotenv_multiline_values() -> Result<()> {
+        let content = "ESCAPED=\"line1\\nline2\"\nSPANNING=\"-----BEGIN KEY-----\nAAAA\n-----END KEY-----\"\nAFTER=value";
+
+        assert_eq!(
+            parse(content)?,
+            vec![
+                entry("ESCAPED", "line1\nline2"),
+                entry("SPANNING", "-----BEGIN KEY-----\nAAAA\n-----END KEY-----"),
+                entry("AFTER", "value"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_entry_round_trips() -> Result<()> {
+        let entries = vec![
+            entry("PLAIN", "db.example.com:5432"),
+            entry("EMPTY", ""),
+            entry("SPACES", "two words # not a comment"),
+            entry("QUOTES", "p@ss \"word\" 'single' \\ $HOME"),
+            entry("DOLLAR", "pa$$word ${HOME} \"double\" \\"),
+            entry(
+                "MULTILINE",
+                "-----BEGIN KEY-----\r\nAAAA\n\t-----END KEY-----",
+            ),
+        ];
+
+        let content: Vec<String> = entries
+            .iter()
+            .map(|(key, value)| format_entry(key, value))
+            .collect();
+
+        assert_eq!(content[0], "PLAIN=db.example.com:5432");
+        assert_eq!(content[1], "EMPTY=");
+        assert_eq!(content[4], r#"DOLLAR='pa$$word ${HOME} "double" \'"#);
+        assert_eq!(parse(&content.join("\n"))?, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dotenv_errors() {
+        assert!(parse("NOT A PAIR").is_err());
+        assert!(parse("=value").is_err());
+        assert!(parse("KEY=\"unclosed").is_err());
+        assert!(parse("KEY='unclosed").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+use anyhow::Result;
+use reqwest::StatusCode;
+use serde_json::json;
+
+use crate::{
+    retry::{self, Failure},
+    timeout,
+};
+
+/// What kind of failure stopped the action. Each kind has a stable exit code, so that scripts
+/// can react to e.g. a revoked access token differently from an unreachable server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The inputs or command line are invalid.
+    Config,
+    /// The server URLs are invalid or inconsistent.
+    InvalidUrl,
+    /// The access token was rejected.
+    Authentication,
+    /// The machine account may not access a project or secret.
+    Authorization,
+    /// A requested secret does not exist.
+    NotFound,
+    /// The server could not be reached, or kept failing after every retry.
+    Transport,
+    /// The `timeout` deadline was exceeded.
+    Timeout,
+    /// Anything else, e.g. failing to write an output file.
+    Other,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::InvalidUrl => 3,
+            ErrorKind::Authentication => 4,
+            ErrorKind::Authorization => 5,
+            ErrorKind::NotFound => 6,
+            ErrorKind::Transport => 7,
+            ErrorKind::Timeout => timeout::DEADLINE_EXCEEDED_EXIT_CODE,
+        }
+    }
+
+    /// A short title for the failure, e.g. for an annotation in the CI system's UI.
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorKind::Config => "Invalid configuration",
+            ErrorKind::InvalidUrl => "Invalid server URL",
+            ErrorKind::Authentication => "Authentication failed",
+            ErrorKind::Authorization => "Access denied",
+            ErrorKind::NotFound => "Secret not found",
+            ErrorKind::Transport => "Bitwarden unreachable",
+            ErrorKind::Timeout => "Timed out",
+            ErrorKind::Other => "Secrets Manager action failed",
+        }
+    }
+
+    /// The kind of a failed request: transient failures mean the server could not be reached,
+    /// otherwise the status tells, falling back to `fallback`.
+    pub fn of_request(error: &anyhow::Error, fallback: ErrorKind) -> ErrorKind {
+        if retry::classify(error) == Failure::Transient {
+            return ErrorKind::Transport;
+        }
+
+        match retry::response_status(error) {
+            Some(StatusCode::UNAUTHORIZED) => ErrorKind::Authentication,
+            Some(StatusCode::FORBIDDEN) => ErrorKind::Authorization,
+            Some(StatusCode::NOT_FOUND) => ErrorKind::NotFound,
+            _ => fallback,
+        }
+    }
+
+    /// The kind of the first error in the chain that has one.
+    pub fn of(error: &anyhow::Error) -> ErrorKind {
+        error
+            .chain()
+            .find_map(|cause| {
+                if cause.is::<timeout::DeadlineExceeded>() {
+                    return Some(ErrorKind::Timeout);
+                }
+                cause.downcast_ref::<Error>().map(|error| error.kind)
+            })
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    /// Attaches this kind to the error, unless it already has one.
+    pub fn wrap(self, error: anyhow::Error) -> anyhow::Error {
+        if ErrorKind::of(&error) != ErrorKind::Other {
+            return error;
+        }
+        Error { kind: self, error }.into()
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Config => "config",
+            ErrorKind::InvalidUrl => "invalid_url",
+            ErrorKind::Authentication => "authentication",
+            ErrorKind::Authorization => "authorization",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Transport => "transport",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Other => "other",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An error with its kind attached, which reads the same as the error itself.
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait ResultExt<T> {
+    /// Attaches `kind` to the error, unless it already has one.
+    fn kind(self, kind: ErrorKind) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn kind(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|error| kind.wrap(error))
+    }
+}
+
+/// A single line describing the error for scripts to parse, e.g.
+/// `sm-action-error: {"kind":"authentication","exit_code":4,"message":"..."}`.
+pub fn machine_readable_line(error: &anyhow::Error) -> String {
+    let kind = ErrorKind::of(error);
+    let details = json!({
+        "kind": kind.to_string(),
+        "exit_code": kind.exit_code(),
+        "message": error.to_string(),
+    });
+    format!("sm-action-error: {details}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sdk_error(status: &str) -> anyhow::Error {
+        anyhow::anyhow!("Received error message from server: [{status}] details")
+    }
+
+    #[test]
+    fn test_of_request() {
+        assert_eq!(
+            ErrorKind::of_request(&sdk_error("401 Unauthorized"), ErrorKind::NotFound),
+            ErrorKind::Authentication
+        );
+        assert_eq!(
+            ErrorKind::of_request(&sdk_error("403 Forbidden"), ErrorKind::NotFound),
+            ErrorKind::Authorization
+        );
+        assert_eq!(
+            ErrorKind::of_request(&sdk_error("404 Not Found"), ErrorKind::Authorization),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            ErrorKind::of_request(&sdk_error("503 Service Unavailable"), ErrorKind::NotFound),
+            ErrorKind::Transport
+        );
+        assert_eq!(
+            ErrorKind::of_request(&sdk_error("400 Bad Request"), ErrorKind::Authentication),
+            ErrorKind::Authentication
+        );
+    }
+
+    #[test]
+    fn test_first_kind_is_kept() {
+        let result: Result<()> = Err(anyhow::anyhow!("Invalid base_url"));
+        let error = result
+            .kind(ErrorKind::InvalidUrl)
+            .kind(ErrorKind::Config)
+            .unwrap_err();
+
+        assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidUrl);
+        assert_eq!(error.to_string(), "Invalid base_url");
+        assert_eq!(ErrorKind::of(&anyhow::anyhow!("other")), ErrorKind::Other);
+
+        let timed_out = anyhow::Error::new(timeout::DeadlineExceeded {
+            timeout: std::time::Duration::from_secs(1),
+        });
+        assert_eq!(
+            ErrorKind::of(&ErrorKind::Config.wrap(timed_out)),
+            ErrorKind::Timeout
+        );
+    }
+
+    #[test]
+    fn test_machine_readable_line() -> Result<()> {
+        let error = ErrorKind::NotFound.wrap(anyhow::anyhow!("Secret \"DB\" is missing"));
+        let line = machine_readable_line(&error);
+
+        let details = line
+            .strip_prefix("sm-action-error: ")
+            .expect("line should be prefixed");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(details)?,
+            json!({ "kind": "not_found", "exit_code": 6, "message": "Secret \"DB\" is missing" })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::BTreeMap, process::ExitStatus};
+
+use anyhow::Result;
+use tokio::process::Command;
+
+use crate::{
+    ci::{ContinuousIntegration, Layer},
+    debug,
+    summary::{Delivery, SummaryEntry},
+};
+
+/// Collects secrets for a child process instead of writing them to the job's environment or step
+/// outputs, where every later step could read them.
+#[derive(Default)]
+pub struct ExecEnvironment {
+    environment: BTreeMap<String, String>,
+}
+
+impl ExecEnvironment {
+    /// The variables to set in the child process.
+    pub fn environment(&self) -> &BTreeMap<String, String> {
+        &self.environment
+    }
+}
+
+impl Layer for ExecEnvironment {
+    fn set_environment(
+        &mut self,
+        _inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        self.environment.insert(name.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    /// Outputs are passed to the child too, so its environment doesn't depend on `set_env`.
+    fn set_output(
+        &mut self,
+        _inner: &mut impl ContinuousIntegration,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        self.environment.insert(name.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    /// Outputs are set in the child's environment, so they need valid variable names too.
+    fn validate_output_name(&self, inner: &impl ContinuousIntegration, name: &str) -> Result<()> {
+        inner.validate_variable_name(name)
+    }
+
+    /// Variables only reach the child, whatever they would otherwise have been set as.
+    fn write_summary(
+        &mut self,
+        inner: &mut impl ContinuousIntegration,
+        entries: &[SummaryEntry],
+    ) -> Result<()> {
+        let entries: Vec<SummaryEntry> = entries
+            .iter()
+            .cloned()
+            .map(|mut entry| {
+                if entry.delivery != Delivery::File {
+                    entry.delivery = Delivery::Command;
+                }
+                entry
+            })
+            .collect();
+        inner.write_summary(&entries)
+    }
+}
+
+/// Variables holding the credentials the action itself used, which the child has no need for.
+const CREDENTIAL_VARIABLES: &[&str] = &[
+    "INPUT_ACCESS_TOKEN",
+    "INPUT_ACCESS_TOKEN_FILE",
+    "SM_ACCESS_TOKEN",
+    "SM_ACCESS_TOKEN_FILE",
+];
+
+/// Runs the command with the secrets added to its environment, forwarding termination signals
+/// to it until it exits.
+pub async fn run_command(
+    command: &[String],
+    environment: &BTreeMap<String, String>,
+) -> Result<ExitStatus> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("No command given"))?;
+    debug!("Running '{program}' with {} secrets", environment.len());
+
+    let mut child = build_command(program, args, environment)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run '{program}'.\nError: {e}"))?;
+
+    wait_forwarding_signals(&mut child).await
+}
+
+/// The child inherits the job's environment, minus the credentials, plus the secrets.
+fn build_command(
+    program: &str,
+    args: &[String],
+    environment: &BTreeMap<String, String>,
+) -> Command {
+    let mut command = Command::new(program);
+    command.args(args);
+    for name in CREDENTIAL_VARIABLES {
+        command.env_remove(name);
+    }
+    command.envs(environment);
+    command
+}
+
+#[cfg(unix)]
+async fn wait_forwarding_signals(child: &mut tokio::process::Child) -> Result<ExitStatus> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut quit = signal(SignalKind::quit())?;
+
+    loop {
+        let signal = tokio::select! {
+            status = child.wait() => return Ok(status?),
+            _ = interrupt.recv() => libc::SIGINT,
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+            _ = quit.recv() => libc::SIGQUIT,
+        };
+
+        if let Some(pid) = child.id() {
+            debug!("Forwarding signal {signal} to process {pid}");
+            // SAFETY: kill has no memory safety requirements
+            unsafe { libc::kill(pid as libc::pid_t, signal) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_forwarding_signals(child: &mut tokio::process::Child) -> Result<ExitStatus> {
+    Ok(child.wait().await?)
+}
+
+/// Exits with the child's exit code. A child killed by a signal is reported the same way by
+/// re-raising the signal, so callers see exactly what they would have without the wrapper.
+pub fn exit_with(status: ExitStatus) -> ! {
+    if let Some(code) = status.code() {
+        std::process::exit(code);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            // SAFETY: restoring the default disposition and raising a signal have no memory
+            // safety requirements
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+            // Signals that don't terminate the process by default use the shell convention
+            std::process::exit(128 + signal);
+        }
+    }
+
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_passes_environment_and_exit_code() -> Result<()> {
+        let environment = BTreeMap::from([("SM_ACTION_TEST_SECRET".to_string(), "7".to_string())]);
+
+        let status = run_command(
+            &args(&["sh", "-c", "exit $SM_ACTION_TEST_SECRET"]),
+            &environment,
+        )
+        .await?;
+
+        assert_eq!(status.code(), Some(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_command_removes_credentials() {
+        let environment = BTreeMap::from([("DB_PASSWORD".to_string(), "secret".to_string())]);
+        let command = build_command("env", &[], &environment);
+
+        let variables: BTreeMap<_, _> = command.as_std().get_envs().collect();
+        for name in CREDENTIAL_VARIABLES {
+            assert_eq!(
+                variables.get(std::ffi::OsStr::new(name)),
+                Some(&None),
+                "{name}"
+            );
+        }
+        // The command may request its own ID token
+        assert_eq!(
+            variables.get(std::ffi::OsStr::new("ACTIONS_ID_TOKEN_REQUEST_TOKEN")),
+            None
+        );
+        assert_eq!(
+            variables.get(std::ffi::OsStr::new("DB_PASSWORD")),
+            Some(&Some(std::ffi::OsStr::new("secret")))
+        );
+    }
+}
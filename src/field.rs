@@ -0,0 +1,154 @@
+use std::fmt;
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+/// Selects a single field from a secret whose value is a JSON document.
+///
+/// Fields are written either as a JSON pointer (`#/credentials/password`) or as a simple
+/// JSONPath (`$.credentials.password`, `$.hosts[0]`), which is converted to a pointer.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JsonField {
+    pointer: String,
+}
+
+impl JsonField {
+    /// Parses a JSON pointer, which must start with `/`.
+    pub fn from_pointer(pointer: &str) -> Result<Self> {
+        if !pointer.starts_with('/') {
+            bail!("JSON pointer must start with '/': {pointer}");
+        }
+
+        Ok(Self {
+            pointer: pointer.to_string(),
+        })
+    }
+
+    /// Parses a JSONPath made up of `.name` and `[index]` segments, starting at `$`.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let Some(mut rest) = path.strip_prefix('$') else {
+            bail!("JSONPath must start with '$': {path}");
+        };
+
+        let mut pointer = String::new();
+        while !rest.is_empty() {
+            let segment;
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                (segment, rest) = after_dot.split_at(end);
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let Some((index, after_index)) = after_bracket.split_once(']') else {
+                    bail!("Unclosed '[' in JSONPath: {path}");
+                };
+                (segment, rest) = (index, after_index);
+            } else {
+                bail!("Unsupported JSONPath, only '.name' and '[index]' are allowed: {path}");
+            }
+
+            if segment.is_empty() {
+                bail!("Empty segment in JSONPath: {path}");
+            }
+
+            // Escape the segment as per RFC 6901
+            pointer.push('/');
+            pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+        }
+
+        if pointer.is_empty() {
+            bail!("JSONPath must select a field: {path}");
+        }
+
+        Ok(Self { pointer })
+    }
+
+    /// Extracts the field from a JSON document. Strings are returned without their quotes, any
+    /// other value is returned as JSON.
+    pub fn extract(&self, document: &str) -> Result<String> {
+        let json: Value = serde_json::from_str(document)
+            .map_err(|e| anyhow::anyhow!("The secret value is not valid JSON: {e}"))?;
+
+        match json.pointer(&self.pointer) {
+            Some(Value::String(value)) => Ok(value.to_owned()),
+            Some(value) => Ok(value.to_string()),
+            None => bail!("The field '{}' does not exist", self.pointer),
+        }
+    }
+}
+
+impl fmt::Display for JsonField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.pointer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"{
+        "host": "db.example.com",
+        "port": 5432,
+        "credentials": { "user": "admin", "password": "hunter2" },
+        "replicas": ["replica-1.example.com", "replica-2.example.com"],
+        "a/b": "slash"
+    }"#;
+
+    #[test]
+    fn test_extract_with_pointer() -> Result<()> {
+        let password = JsonField::from_pointer("/credentials/password")?;
+        let port = JsonField::from_pointer("/port")?;
+        let credentials = JsonField::from_pointer("/credentials")?;
+        let slash = JsonField::from_pointer("/a~1b")?;
+
+        assert_eq!(password.extract(DOCUMENT)?, "hunter2");
+        assert_eq!(port.extract(DOCUMENT)?, "5432");
+        assert_eq!(
+            credentials.extract(DOCUMENT)?,
+            r#"{"password":"hunter2","user":"admin"}"#
+        );
+        assert_eq!(slash.extract(DOCUMENT)?, "slash");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_with_path() -> Result<()> {
+        let host = JsonField::from_path("$.host")?;
+        let user = JsonField::from_path("$.credentials.user")?;
+        let replica = JsonField::from_path("$.replicas[1]")?;
+
+        assert_eq!(host.extract(DOCUMENT)?, "db.example.com");
+        assert_eq!(user.extract(DOCUMENT)?, "admin");
+        assert_eq!(replica.extract(DOCUMENT)?, "replica-2.example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        assert!(JsonField::from_pointer("password").is_err());
+        assert!(JsonField::from_path("host").is_err());
+        assert!(JsonField::from_path("$").is_err());
+        assert!(JsonField::from_path("$.replicas[0").is_err());
+        assert!(JsonField::from_path("$..host").is_err());
+    }
+
+    #[test]
+    fn test_extract_errors() -> Result<()> {
+        let missing = JsonField::from_pointer("/missing")?;
+
+        assert!(
+            missing
+                .extract(DOCUMENT)
+                .unwrap_err()
+                .to_string()
+                .contains("does not exist")
+        );
+        assert!(
+            missing
+                .extract("KEY=value")
+                .unwrap_err()
+                .to_string()
+                .contains("not valid JSON")
+        );
+        Ok(())
+    }
+}
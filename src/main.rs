@@ -1,110 +1,1007 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::Result;
 use bitwarden::{
     Client, ClientSettings, DeviceType,
     auth::login::AccessTokenLoginRequest,
-    secrets_manager::{SecretsClientExt, secrets::SecretsGetRequest},
+    secrets_manager::{
+        SecretsClientExt,
+        secrets::{
+            SecretGetRequest, SecretIdentifiersByProjectRequest, SecretIdentifiersRequest,
+            SecretResponse, SecretsGetRequest,
+        },
+    },
 };
+use cli::{Cli, Command};
 use config::{Config, infer_urls};
+use error::{ErrorKind, ResultExt};
+use exec::ExecEnvironment;
+use field::JsonField;
+use retry::{RetryPolicy, retry};
+use structured::StructuredFormat;
+use summary::{Delivery, SecretStatus, SummaryEntry};
 use uuid::Uuid;
 
-use ci::{ContinuousIntegration, GithubActionsRunner};
+use ci::{
+    AzurePipelinesRunner, CiBackend, Collecting, ContinuousIntegration, GithubActionsRunner,
+    GitlabCiRunner, Layered, LocalRunner, Masking, OutputOnly, Protecting,
+};
 
 mod ci;
+mod cleanup;
+mod cli;
 mod config;
+mod dotenv;
+mod error;
+mod exec;
+mod field;
+mod retry;
+mod structured;
+mod summary;
+#[cfg(test)]
+mod test_server;
+mod timeout;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    let mut github_runner = GithubActionsRunner::new()?;
-    run(&mut github_runner).await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match Cli::parse(&args).kind(ErrorKind::Config) {
+        Ok(cli) => run_cli(&cli).await,
+        Err(e) => Err(print_error(e)),
+    };
+
+    // Each kind of failure has its own exit code, and a line scripts can parse
+    if let Err(e) = result {
+        eprintln!("{}", error::machine_readable_line(&e));
+        std::process::exit(ErrorKind::of(&e).exit_code());
+    }
+    Ok(())
 }
 
-async fn run<T: ContinuousIntegration>(ci: &mut T) -> Result<()> {
-    // this doubles as a way to validate the binaries in CI
-    if std::env::args().any(|arg| arg == "--version") {
-        println!("{VERSION}");
-        return Ok(());
+async fn run_cli(cli: &Cli) -> Result<()> {
+    // --help and --version don't need a CI system either
+    let backend = if cli.is_local() || matches!(cli.command, Command::Help | Command::Version) {
+        CiBackend::None
+    } else {
+        CiBackend::detect()
+    };
+
+    match backend {
+        CiBackend::GithubActions => {
+            let mut runner = GithubActionsRunner::new().map_err(print_error)?;
+            run(&cli.command, &mut runner).await
+        }
+        CiBackend::GitlabCi => {
+            let mut runner = GitlabCiRunner::new().map_err(print_error)?;
+            run(&cli.command, &mut runner).await
+        }
+        CiBackend::AzurePipelines => {
+            let mut runner = AzurePipelinesRunner::new().map_err(print_error)?;
+            run(&cli.command, &mut runner).await
+        }
+        CiBackend::None => {
+            let mut runner = LocalRunner::new(cli.inputs.clone(), cli.format.unwrap_or_default());
+            run(&cli.command, &mut runner).await?;
+            runner.write_outputs(&mut std::io::stdout())
+        }
+    }
+}
+
+/// Prints an error that happened before there was a CI system to report it through.
+fn print_error(e: anyhow::Error) -> anyhow::Error {
+    eprintln!("Error: {e:?}");
+    e
+}
+
+/// Runs the command, reporting any failure through the CI system.
+async fn run<T: ContinuousIntegration>(command: &Command, ci: &mut T) -> Result<()> {
+    let result = execute(command, ci).await;
+    if let Err(e) = &result {
+        // Out of any group, so the error isn't collapsed out of sight
+        ci.end_group();
+        ci.error(ErrorKind::of(e).title(), &format!("{e:?}"));
     }
+    result
+}
+
+async fn execute<T: ContinuousIntegration>(command: &Command, ci: &mut T) -> Result<()> {
+    match command {
+        Command::Run => set_secrets(ci).await,
+
+        // runs a command with the secrets in its environment only, then exits with its status
+        Command::Exec(command) => {
+            let mut runner = Layered::new(ci, ExecEnvironment::default());
+            set_secrets(&mut runner).await?;
 
-    let config = Config::new(ci)?;
-    let (api_url, identity_url) = infer_urls(&config)?;
+            runner.log(&format!("Running {}...", command[0]));
+            let status = exec::run_command(command, runner.layer().environment()).await?;
+            exec::exit_with(status);
+        }
+
+        // runs as the action's post step, after the rest of the job
+        Command::Post => cleanup::run_post(ci),
+
+        // this doubles as a way to validate the binaries in CI
+        Command::Version => {
+            println!("{VERSION}");
+            Ok(())
+        }
+
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
+
+/// Retrieves the requested secrets and delivers them through the CI system, within the
+/// configured timeout.
+async fn set_secrets<T: ContinuousIntegration>(ci: &mut T) -> Result<()> {
+    let config = Config::new(ci).kind(ErrorKind::Config)?;
+    timeout::with_deadline(config.timeout, set_secrets_with(ci, config)).await
+}
+
+async fn set_secrets_with<T: ContinuousIntegration>(ci: &mut T, config: Config) -> Result<()> {
+    let ci = &mut Layered::new(ci, Masking::new(config.mask_derivatives));
+    let ci = &mut Layered::new(
+        ci,
+        Protecting::new(&config.protected_names, &config.allowed_protected_names),
+    );
+    let ci = &mut Layered::new(ci, OutputOnly::new(!config.set_env));
+    let ci = &mut Layered::new(ci, Collecting::default());
+    let (api_url, identity_url) = infer_urls(&config).kind(ErrorKind::InvalidUrl)?;
 
     let client = Client::new(Some(ClientSettings {
-        identity_url,
-        api_url,
+        identity_url: identity_url.clone(),
+        api_url: api_url.clone(),
         user_agent: "bitwarden/sm-action".to_string(),
         device_type: DeviceType::SDK,
         ..Default::default()
     }));
 
-    println!("Parsing secrets input...");
-    let id_to_name_map = parse_secret_input(config.secrets).map_err(|_| {
-        anyhow::anyhow!("Failed to parse secrets input. Ensure the format is 'UUID > Name'.")
-    })?;
+    let policy = RetryPolicy::new(config.retries);
 
-    println!("Authenticating with Bitwarden...");
-    let auth_result = client
-        .auth()
-        .login_access_token(&AccessTokenLoginRequest {
-            access_token: config.access_token,
-            state_file: None,
-        })
-        .await;
+    ci.log("Parsing secrets input...");
+    let secret_requests = parse_secret_input(ci, config.secrets).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to parse secrets input. Ensure the format is 'UUID > Name', 'key:KEY > Name' or 'project:UUID > PREFIX_'.\nError: {e}"
+        )
+    })
+    .kind(ErrorKind::Config)?;
+
+    ci.start_group("Authenticating with Bitwarden");
+    if let Some(connect_timeout) = config.connect_timeout.filter(|_| !timeout::uses_proxy()) {
+        for url in [&identity_url, &api_url] {
+            retry(ci, &policy, || {
+                timeout::check_connection(url, connect_timeout)
+            })
+            .await
+            .map_err(|e| {
+                ErrorKind::of_request(&e, ErrorKind::InvalidUrl)
+                    .wrap(anyhow::anyhow!("Could not connect to {url}.\nError: {e}"))
+            })?;
+        }
+    }
+    ci.log("Logging in with the access token...");
+    let login_request = AccessTokenLoginRequest {
+        access_token: config.access_token,
+        state_file: None,
+    };
+    let auth_result = retry(ci, &policy, || async {
+        Ok(client.auth().login_access_token(&login_request).await?)
+    })
+    .await;
 
     if let Err(e) = auth_result {
-        return Err(anyhow::anyhow!(
-            "Authentication with Bitwarden failed.\nError: {e}",
+        return Err(
+            ErrorKind::of_request(&e, ErrorKind::Authentication).wrap(anyhow::anyhow!(
+                "Authentication with Bitwarden failed.\nError: {e}",
+            )),
+        );
+    }
+    ci.end_group();
+
+    ci.start_group("Retrieving secrets");
+    let mut id_to_name_map = secret_requests.ids;
+
+    if !secret_requests.keys.is_empty() {
+        ci.log("Resolving secret keys...");
+    }
+
+    // Unscoped keys are looked up across the whole organization, which only needs listing once
+    let mut organization_secrets = None;
+    for key_request in secret_requests.keys.iter() {
+        let id = match key_request.project_id {
+            Some(project_id) => resolve_secret_key(
+                &key_request.key,
+                &list_project_secrets(ci, &client, &policy, project_id).await?,
+            )?,
+            None => {
+                if organization_secrets.is_none() {
+                    organization_secrets =
+                        Some(list_organization_secrets(ci, &client, &policy).await?);
+                }
+                resolve_secret_key(
+                    &key_request.key,
+                    organization_secrets.as_deref().unwrap_or_default(),
+                )?
+            }
+        };
+        debug!("Resolved key '{}' to {id}", key_request.key);
+
+        if let Some(old_value) = id_to_name_map.insert(id, key_request.destination.clone()) {
+            ci.warning(
+                "Duplicate secret",
+                &format!(
+                    "The key '{}' resolves to secret {id}, which is also requested as {old_value}. Only {} will be set.",
+                    key_request.key, key_request.destination
+                ),
+            );
+        }
+    }
+
+    for (project_id, prefix) in secret_requests.projects.iter() {
+        ci.log(&format!("Listing secrets in project {project_id}..."));
+        let project_secrets = list_project_secrets(ci, &client, &policy, *project_id).await?;
+        let project_names =
+            name_project_secrets(ci, prefix, project_secrets).kind(ErrorKind::Config)?;
+
+        for (id, name) in project_names {
+            // Secrets that were also requested by UUID keep their explicit name
+            id_to_name_map
+                .entry(id)
+                .or_insert(Destination::Variable(name));
+        }
+    }
+
+    let fields = secret_requests.fields;
+
+    let mut secret_ids: Vec<Uuid> = id_to_name_map
+        .keys()
+        .chain(fields.iter().map(|field| &field.id))
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    // Sorted, so that the requests don't depend on the order of the hash set
+    secret_ids.sort();
+
+    // Carry on without any, so the summary and output files still say so
+    if secret_ids.is_empty() {
+        ci.log("No secrets found to set.");
+    }
+
+    let (optional_ids, required_ids): (Vec<Uuid>, Vec<Uuid>) = secret_ids
+        .iter()
+        .partition(|id| secret_requests.defaults.contains_key(id));
+
+    let mut secrets = Vec::with_capacity(secret_ids.len());
+    if !required_ids.is_empty() {
+        secrets.extend(get_secrets(ci, &client, &policy, &required_ids).await?);
+    }
+
+    // Optional secrets are retrieved one at a time so a missing one can't fail the others
+    for id in optional_ids {
+        secrets.extend(get_secret_if_found(ci, &client, &policy, id).await?);
+    }
+
+    let requested = id_to_name_map
+        .iter()
+        .map(|(id, destination)| (*id, destination.clone()))
+        .chain(
+            fields
+                .iter()
+                .map(|field| (field.id, field.destination.clone())),
+        );
+    let (missing_optional, missing): (Vec<_>, Vec<_>) =
+        find_missing_secrets(requested, secrets.iter().map(|s| s.id))
+            .into_iter()
+            .partition(|(id, _)| secret_requests.defaults.contains_key(id));
+    if !missing.is_empty() && !config.allow_missing {
+        return Err(ErrorKind::NotFound.wrap(anyhow::anyhow!(
+            "The following secrets were not returned by Bitwarden: {}. Please check the machine account has access to them, or set 'allow_missing' to continue without them.",
+            describe_missing_secrets(&missing)
+        )));
+    }
+
+    ci.end_group();
+
+    ci.start_group("Setting secrets");
+    let mut summary = Vec::new();
+    for secret in secrets.iter() {
+        let status = SecretStatus::Retrieved {
+            revision_date: secret
+                .revision_date
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+        };
+
+        if let Some(destination) = id_to_name_map.get(&secret.id) {
+            deliver_secret(ci, destination, &secret.value, config.set_env)?;
+            summary.push(destination.summary_entry(
+                secret.id,
+                destination.to_string(),
+                config.set_env,
+                status.clone(),
+            ));
+        }
+
+        let secret_fields: Vec<&FieldRequest> = fields
+            .iter()
+            .filter(|field| field.id == secret.id)
+            .collect();
+        if !secret_fields.is_empty() {
+            // Mask the whole document too, as only its fields are masked when delivering them
+            ci.mask_value(&secret.value);
+        }
+
+        for field_request in secret_fields {
+            let value = field_request.field.extract(&secret.value).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to read {} from secret {}.\nError: {e}",
+                    field_request.field,
+                    secret.id
+                )
+            })?;
+            deliver_secret(ci, &field_request.destination, &value, config.set_env)?;
+            summary.push(field_request.destination.summary_entry(
+                secret.id,
+                format!("{} > {}", field_request.field, field_request.destination),
+                config.set_env,
+                status.clone(),
+            ));
+        }
+    }
+
+    if !missing.is_empty() {
+        ci.warning(
+            "Secrets not found",
+            &format!(
+                "The following secrets were not returned by Bitwarden and have been set to an empty value: {}",
+                describe_missing_secrets(&missing)
+            ),
+        );
+        for (id, destination) in missing.iter() {
+            deliver_secret(ci, destination, "", config.set_env)?;
+            summary.push(destination.summary_entry(
+                *id,
+                destination.to_string(),
+                config.set_env,
+                SecretStatus::Missing,
+            ));
+        }
+    }
+
+    for (id, destination) in missing_optional.iter() {
+        ci.warning(
+            "Default value used",
+            &format!("Optional secret {destination} was not found; using its default value."),
+        );
+        let default = secret_requests
+            .defaults
+            .get(id)
+            .map(String::as_str)
+            .unwrap_or_default();
+        deliver_secret(ci, destination, default, config.set_env)?;
+        summary.push(destination.summary_entry(
+            *id,
+            destination.to_string(),
+            config.set_env,
+            SecretStatus::DefaultUsed,
         ));
     }
 
-    let secret_ids: Vec<Uuid> = id_to_name_map.keys().cloned().collect();
+    // The summary is a record for reviewers, so failing to write it doesn't fail the step
+    summary.sort_by(|a, b| a.target.cmp(&b.target));
+    if let Err(e) = ci.write_summary(&summary) {
+        ci.warning("Job summary not written", &e.to_string());
+    }
 
-    let secrets = client
-        .secrets()
-        .get_by_ids(SecretsGetRequest { ids: secret_ids })
-        .await.map_err(|e| {
-            anyhow::anyhow!(
-                "The secrets provided could not be found. Please check the machine account has access to the secret UUIDs provided.\nError: {e}",
-            )
-        })?;
+    if let Some(dotenv_file) = config.dotenv_file.as_deref() {
+        write_dotenv_file(ci, Path::new(dotenv_file))?;
+    }
 
-    println!("Setting secrets...");
-    for secret in secrets.data.iter() {
-        id_to_name_map
-            .get(&secret.id)
-            .map(|name| set_secret(ci, name, &secret.value, config.set_env))
-            .transpose()?;
+    if let Some(format) = config.output_format {
+        write_structured_output(ci, format, config.output_file.as_deref().map(Path::new))?;
     }
 
-    println!("Completed setting secrets.");
+    ci.end_group();
+    ci.log("Completed setting secrets.");
+
+    Ok(())
+}
+
+/// Writes every variable that was set to a dotenv file only readable by the current user.
+fn write_dotenv_file<T: ContinuousIntegration>(
+    ci: &mut Layered<T, Collecting>,
+    path: &Path,
+) -> Result<()> {
+    let content: String = ci
+        .layer()
+        .variables()
+        .iter()
+        .map(|(name, value)| dotenv::format_entry(name, value) + "\n")
+        .collect();
+
+    let written_path = write_job_file(ci, path, &content)?;
+    ci.log(&format!(
+        "Wrote {} variables to {}",
+        ci.layer().variables().len(),
+        written_path.display()
+    ));
+    Ok(())
+}
+
+/// Provides every variable that was set as a single document, either in a file or as one output
+/// named after the format.
+fn write_structured_output<T: ContinuousIntegration>(
+    ci: &mut Layered<T, Collecting>,
+    format: StructuredFormat,
+    path: Option<&Path>,
+) -> Result<()> {
+    let variables = ci.layer().variables().to_vec();
 
+    // Values appear escaped in the document, which masking the values alone wouldn't catch
+    for (_, value) in variables.iter().filter(|(_, value)| !value.is_empty()) {
+        ci.mask_value(value);
+    }
+    for escaped in structured::escaped_values(&variables) {
+        ci.mask_value(&escaped);
+    }
+
+    let document = structured::render(format, &variables);
+    match path {
+        Some(path) => {
+            let written_path = write_job_file(ci, path, &document)?;
+            ci.log(&format!(
+                "Wrote {format} document to {}",
+                written_path.display()
+            ));
+        }
+        None => {
+            ci.set_output(&format.to_string(), &document)?;
+            debug!("Successfully wrote {format} document to output file");
+        }
+    }
     Ok(())
 }
 
+/// Writes a file only readable by the current user, to be removed by the post step.
+fn write_job_file<T: ContinuousIntegration>(ci: &T, path: &Path, content: &str) -> Result<PathBuf> {
+    let written_path = ci::write_private_file(path, content)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}.\nError: {e}", path.display()))?;
+    if let Some(manifest_path) = ci.file_manifest_path() {
+        cleanup::record_file(&manifest_path, &written_path)?;
+    }
+    Ok(written_path)
+}
+
+/// Retrieves the secrets with the given ids in a single request. The server fails the whole
+/// request if any of them can't be found, so they are then retrieved one at a time instead,
+/// leaving out the ones that can't be found for the caller to name.
+async fn get_secrets<T: ContinuousIntegration>(
+    ci: &mut T,
+    client: &Client,
+    policy: &RetryPolicy,
+    ids: &[Uuid],
+) -> Result<Vec<SecretResponse>> {
+    let result = retry(ci, policy, || async {
+        Ok(client
+            .secrets()
+            .get_by_ids(SecretsGetRequest { ids: ids.to_vec() })
+            .await?)
+    })
+    .await;
+
+    let error = match result {
+        Ok(response) => return Ok(response.data),
+        Err(e) => e,
+    };
+    if ErrorKind::of_request(&error, ErrorKind::Other) != ErrorKind::NotFound {
+        return Err(ErrorKind::of_request(&error, ErrorKind::NotFound).wrap(anyhow::anyhow!(
+            "The secrets provided could not be found. Please check the machine account has access to the secret UUIDs provided.\nError: {error}",
+        )));
+    }
+
+    debug!("Not every secret could be found; retrieving them one at a time");
+    let mut secrets = Vec::with_capacity(ids.len());
+    for id in ids {
+        secrets.extend(get_secret_if_found(ci, client, policy, *id).await?);
+    }
+    Ok(secrets)
+}
+
+/// Retrieves a single secret, or `None` if it doesn't exist or the machine account can't see it.
+/// Any other failure, e.g. a revoked access token, is an error.
+async fn get_secret_if_found<T: ContinuousIntegration>(
+    ci: &mut T,
+    client: &Client,
+    policy: &RetryPolicy,
+    id: Uuid,
+) -> Result<Option<SecretResponse>> {
+    let request = SecretGetRequest { id };
+    match retry(ci, policy, || async {
+        Ok(client.secrets().get(&request).await?)
+    })
+    .await
+    {
+        Ok(secret) => Ok(Some(secret)),
+        Err(e) if ErrorKind::of_request(&e, ErrorKind::Other) == ErrorKind::NotFound => {
+            debug!("Secret {id} could not be found: {e}");
+            Ok(None)
+        }
+        Err(e) => Err(
+            ErrorKind::of_request(&e, ErrorKind::Other).wrap(anyhow::anyhow!(
+                "Secret {id} could not be retrieved.\nError: {e}"
+            )),
+        ),
+    }
+}
+
+/// Lists the ids and keys of every secret in a project.
+async fn list_project_secrets<T: ContinuousIntegration>(
+    ci: &mut T,
+    client: &Client,
+    policy: &RetryPolicy,
+    project_id: Uuid,
+) -> Result<Vec<(Uuid, String)>> {
+    let request = SecretIdentifiersByProjectRequest { project_id };
+    let secrets = retry(ci, policy, || async {
+        Ok(client.secrets().list_by_project(&request).await?)
+    })
+    .await
+    .map_err(|e| {
+        ErrorKind::of_request(&e, ErrorKind::Authorization).wrap(anyhow::anyhow!(
+            "The secrets in project {project_id} could not be listed. Please check the machine account has access to the project.\nError: {e}",
+        ))
+    })?;
+
+    Ok(secrets
+        .data
+        .into_iter()
+        .map(|secret| (secret.id, secret.key))
+        .collect())
+}
+
+/// Lists the ids and keys of every secret the machine account can access.
+async fn list_organization_secrets<T: ContinuousIntegration>(
+    ci: &mut T,
+    client: &Client,
+    policy: &RetryPolicy,
+) -> Result<Vec<(Uuid, String)>> {
+    let organization_id = client
+        .internal
+        .get_access_token_organization()
+        .ok_or_else(|| anyhow::anyhow!("The access token is not linked to an organization"))
+        .kind(ErrorKind::Authentication)?;
+
+    let request = SecretIdentifiersRequest {
+        organization_id: organization_id.into(),
+    };
+    let secrets = retry(ci, policy, || async {
+        Ok(client.secrets().list(&request).await?)
+    })
+    .await
+    .map_err(|e| {
+        ErrorKind::of_request(&e, ErrorKind::Authorization).wrap(anyhow::anyhow!(
+            "The secrets could not be listed.\nError: {e}"
+        ))
+    })?;
+
+    Ok(secrets
+        .data
+        .into_iter()
+        .map(|secret| (secret.id, secret.key))
+        .collect())
+}
+
+/// Finds the id of the only secret with the given key.
+fn resolve_secret_key(key: &str, candidates: &[(Uuid, String)]) -> Result<Uuid> {
+    let matches: Vec<Uuid> = candidates
+        .iter()
+        .filter(|(_, candidate_key)| candidate_key == key)
+        .map(|(id, _)| *id)
+        .collect();
+
+    match matches.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(ErrorKind::NotFound.wrap(anyhow::anyhow!(
+            "No secret with the key '{key}' was found. Please check the machine account has access to the secret."
+        ))),
+        _ => Err(ErrorKind::Config.wrap(anyhow::anyhow!(
+            "The key '{key}' matches more than one secret: {}. Reference the secret by UUID or scope the key to a project with 'key:PROJECT_UUID/{key}'.",
+            matches
+                .iter()
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// A secret requested by its Bitwarden key instead of its UUID.
+#[derive(Debug, PartialEq)]
+struct KeyRequest {
+    /// Limits the lookup to a single project, which is required when the key isn't unique.
+    project_id: Option<Uuid>,
+    key: String,
+    destination: Destination,
+}
+
+/// Finds the requested secrets that are absent from the response, sorted by destination.
+fn find_missing_secrets<D: Ord>(
+    requested: impl IntoIterator<Item = (Uuid, D)>,
+    returned_ids: impl IntoIterator<Item = Uuid>,
+) -> Vec<(Uuid, D)> {
+    let returned_ids: HashSet<Uuid> = returned_ids.into_iter().collect();
+
+    let mut missing: Vec<(Uuid, D)> = requested
+        .into_iter()
+        .filter(|(id, _)| !returned_ids.contains(id))
+        .collect();
+    missing.sort_by(|a, b| a.1.cmp(&b.1));
+
+    missing
+}
+
+fn describe_missing_secrets<D: fmt::Display>(missing: &[(Uuid, D)]) -> String {
+    missing
+        .iter()
+        .map(|(id, name)| format!("{name} ({id})"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Where the value of a secret should be delivered to.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Destination {
+    /// Set as an environment variable and output with this name.
+    Variable(String),
+    /// Written to a file, optionally exporting its path as a variable.
+    File {
+        path: PathBuf,
+        variable: Option<String>,
+    },
+    /// Parsed as a dotenv file, setting every entry as its own variable with this prefix.
+    Dotenv { prefix: String },
+}
+
+const FILE_PREFIX: &str = "file:";
+const DOTENV_PREFIX: &str = "dotenv:";
+
+impl Destination {
+    /// Parses the part of a line after the `>`, e.g. `NAME`, `file:PATH [> NAME]` or
+    /// `dotenv:[PREFIX]`.
+    fn parse(desired_name: &str) -> Result<Self> {
+        if let Some(prefix) = desired_name.strip_prefix(DOTENV_PREFIX) {
+            return Ok(Destination::Dotenv {
+                prefix: prefix.trim().to_string(),
+            });
+        }
+
+        let Some(file_part) = desired_name.strip_prefix(FILE_PREFIX) else {
+            return Ok(Destination::Variable(desired_name.to_string()));
+        };
+
+        let (path, variable) = match file_part.split_once('>') {
+            Some((path, variable)) => (path.trim(), Some(variable.trim().to_string())),
+            None => (file_part.trim(), None),
+        };
+
+        if path.is_empty() {
+            anyhow::bail!("Missing file path: {desired_name}");
+        }
+
+        Ok(Destination::File {
+            path: PathBuf::from(path),
+            variable,
+        })
+    }
+
+    /// Describes the delivery of a secret to this destination for the job summary.
+    fn summary_entry(
+        &self,
+        id: Uuid,
+        target: String,
+        set_env: bool,
+        status: SecretStatus,
+    ) -> SummaryEntry {
+        let delivery = match self {
+            Destination::File { .. } => Delivery::File,
+            _ if set_env => Delivery::Environment,
+            _ => Delivery::Output,
+        };
+
+        SummaryEntry {
+            id,
+            target,
+            delivery,
+            status,
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Variable(name) => write!(f, "{name}"),
+            Destination::File { path, .. } => write!(f, "{FILE_PREFIX}{}", path.display()),
+            Destination::Dotenv { prefix } => write!(f, "{DOTENV_PREFIX}{prefix}"),
+        }
+    }
+}
+
+/// A single field of a JSON secret, requested with `UUID#/pointer` or `UUID.$.path`.
+#[derive(Debug, PartialEq)]
+struct FieldRequest {
+    id: Uuid,
+    field: JsonField,
+    destination: Destination,
+}
+
+/// The secrets requested through the `secrets` input.
+#[derive(Debug, Default)]
+struct SecretRequests {
+    /// Individual secrets, mapped to where they should be delivered.
+    ids: HashMap<Uuid, Destination>,
+    /// Optional secrets, mapped to the value to use when they can't be retrieved.
+    defaults: HashMap<Uuid, String>,
+    /// Fields to extract from JSON secrets. A secret may have any number of fields requested.
+    fields: Vec<FieldRequest>,
+    /// Secrets to look up by key before they can be retrieved.
+    keys: Vec<KeyRequest>,
+    /// Projects to fetch every secret from, mapped to the prefix for the secrets' keys.
+    projects: HashMap<Uuid, String>,
+}
+
+const PROJECT_PREFIX: &str = "project:";
+const KEY_PREFIX: &str = "key:";
+
 /// Parses the secret input from the GitHub Actions environment variable.
-fn parse_secret_input(secret_lines: Vec<String>) -> Result<HashMap<Uuid, String>> {
-    let mut map: HashMap<Uuid, String> = HashMap::with_capacity(secret_lines.capacity());
+fn parse_secret_input<T: ContinuousIntegration>(
+    ci: &mut T,
+    secret_lines: Vec<String>,
+) -> Result<SecretRequests> {
+    let mut requests = SecretRequests {
+        ids: HashMap::with_capacity(secret_lines.capacity()),
+        defaults: HashMap::new(),
+        fields: Vec::new(),
+        keys: Vec::new(),
+        projects: HashMap::new(),
+    };
+
+    for (index, line) in secret_lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        parse_secret_line(ci, &mut requests, line)
+            .map_err(|e| anyhow::anyhow!("Line {}: {e}", index + 1))?;
+    }
+
+    Ok(requests)
+}
 
-    for line in secret_lines.iter() {
-        debug!("Parsing line: {line}");
-        let uuid_part = line.split('>').next().unwrap_or_default().trim();
-        let uuid = Uuid::from_str(uuid_part)
-            .map_err(|_| anyhow::anyhow!("Invalid UUID format: {uuid_part}"))?;
+/// Parses a single line of the secrets input into the requests.
+fn parse_secret_line<T: ContinuousIntegration>(
+    ci: &mut T,
+    requests: &mut SecretRequests,
+    line: &str,
+) -> Result<()> {
+    debug!("Parsing line: {line}");
+    let (uuid_part, name_part) = line.split_once('>').unwrap_or((line, ""));
+    let uuid_part = uuid_part.trim();
 
-        let desired_name = line.split('>').nth(1).unwrap_or_default().trim();
+    // `NAME ? default` marks a secret as optional, with everything after the `?` as its default
+    let (desired_name, default) = match name_part.split_once('?') {
+        Some((name, default)) => (name.trim(), Some(default.trim())),
+        None => (name_part.trim(), None),
+    };
+
+    if default.is_some() && Uuid::from_str(uuid_part).is_err() {
+        anyhow::bail!("Only whole secrets referenced by UUID can be optional: {line}");
+    }
+
+    if let Some(project_part) = uuid_part.strip_prefix(PROJECT_PREFIX) {
+        let project_id = Uuid::from_str(project_part.trim())
+            .map_err(|_| anyhow::anyhow!("Invalid project UUID format: {project_part}"))?;
+
+        // The prefix starts every name, so it must be valid at the start of a name itself
+        if !desired_name.is_empty() {
+            ci.validate_variable_name(desired_name)?;
+        }
 
-        if let Some(old_value) = map.insert(uuid, desired_name.to_string()) {
-            eprintln!(
-                "Warning: Duplicate UUID found: {uuid}. Old value: {old_value}, New value: {desired_name}"
+        if requests
+            .projects
+            .insert(project_id, desired_name.to_string())
+            .is_some()
+        {
+            ci.warning(
+                "Duplicate project",
+                &format!("Project {project_id} is requested more than once. Only the last prefix is used."),
             );
         }
+        return Ok(());
+    }
+
+    if let Some(key_part) = uuid_part.strip_prefix(KEY_PREFIX) {
+        let key_request = parse_key_request(key_part, desired_name)?;
+        validate_destination(ci, &key_request.destination)?;
+        requests.keys.push(key_request);
+        return Ok(());
+    }
+
+    if let Some((id_part, field)) = split_json_field(uuid_part)? {
+        let id = Uuid::from_str(id_part.trim())
+            .map_err(|_| anyhow::anyhow!("Invalid UUID format: {id_part}"))?;
+
+        let destination = Destination::parse(desired_name)?;
+        validate_destination(ci, &destination)?;
+        requests.fields.push(FieldRequest {
+            id,
+            field,
+            destination,
+        });
+        return Ok(());
+    }
+
+    let uuid = Uuid::from_str(uuid_part)
+        .map_err(|_| anyhow::anyhow!("Invalid UUID format: {uuid_part}"))?;
+
+    let destination = Destination::parse(desired_name)?;
+    validate_destination(ci, &destination)?;
+    if let Some(old_value) = requests.ids.insert(uuid, destination) {
+        ci.warning(
+            "Duplicate secret",
+            &format!(
+                "Secret {uuid} is requested more than once, as {old_value} and {desired_name}. Only {desired_name} will be set."
+            ),
+        );
+    }
+
+    match default {
+        Some(default) => requests.defaults.insert(uuid, default.to_string()),
+        None => requests.defaults.remove(&uuid),
+    };
+
+    Ok(())
+}
+
+/// Checks that the variables a destination sets have names the CI system accepts.
+fn validate_destination<T: ContinuousIntegration>(ci: &T, destination: &Destination) -> Result<()> {
+    match destination {
+        Destination::Variable(name) => ci.validate_variable_name(name),
+        Destination::File {
+            variable: Some(variable),
+            ..
+        } => ci.validate_variable_name(variable),
+        Destination::File { variable: None, .. } => Ok(()),
+        // Without a prefix, the names come from the dotenv secret and are checked on delivery
+        Destination::Dotenv { prefix } if prefix.is_empty() => Ok(()),
+        Destination::Dotenv { prefix } => ci.validate_variable_name(prefix),
+    }
+}
+
+/// Splits a `UUID#/pointer` or `UUID.$.path` reference into the UUID and the selected field.
+/// Returns `None` for references to a whole secret.
+fn split_json_field(reference: &str) -> Result<Option<(&str, JsonField)>> {
+    if let Some((id, pointer)) = reference.split_once('#') {
+        return Ok(Some((id, JsonField::from_pointer(pointer.trim())?)));
+    }
+
+    if let Some(index) = reference.find(".$") {
+        let (id, path) = reference.split_at(index);
+        return Ok(Some((id, JsonField::from_path(path[1..].trim())?)));
+    }
+
+    Ok(None)
+}
+
+/// Parses the `[PROJECT_UUID/]KEY` part of a `key:` line. Without a name, the key is used as is.
+fn parse_key_request(key_part: &str, desired_name: &str) -> Result<KeyRequest> {
+    // Keys may contain slashes themselves, so only treat the first segment as a project scope
+    // if it is a UUID
+    let (project_id, key) = key_part
+        .split_once('/')
+        .and_then(|(project, key)| {
+            Uuid::from_str(project.trim())
+                .ok()
+                .map(|project_id| (Some(project_id), key))
+        })
+        .unwrap_or((None, key_part));
+
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("Missing secret key: {key_part}");
     }
 
-    Ok(map)
+    let destination = if desired_name.is_empty() {
+        Destination::Variable(key.to_string())
+    } else {
+        Destination::parse(desired_name)?
+    };
+
+    Ok(KeyRequest {
+        project_id,
+        key: key.to_string(),
+        destination,
+    })
+}
+
+/// Names the secrets listed from a project after their Bitwarden keys, prepending the prefix.
+/// Fails if any of the resulting names is not a valid variable name for the CI system.
+fn name_project_secrets<T: ContinuousIntegration>(
+    ci: &T,
+    prefix: &str,
+    secrets: impl IntoIterator<Item = (Uuid, String)>,
+) -> Result<Vec<(Uuid, String)>> {
+    let mut named = Vec::new();
+    let mut invalid = Vec::new();
+
+    for (id, key) in secrets {
+        let name = format!("{prefix}{key}");
+        if ci.validate_variable_name(&name).is_ok() {
+            named.push((id, name));
+        } else {
+            invalid.push(format!("'{name}' ({id})"));
+        }
+    }
+
+    if !invalid.is_empty() {
+        anyhow::bail!(
+            "The following project secrets do not have keys that are valid variable names: {}. Request them by UUID with an explicit name instead.",
+            invalid.join(", ")
+        );
+    }
+
+    Ok(named)
+}
+
+/// Delivers the value of a secret to its destination.
+fn deliver_secret<T: ContinuousIntegration>(
+    ci: &mut T,
+    destination: &Destination,
+    secret_value: &str,
+    set_env: bool,
+) -> Result<()> {
+    match destination {
+        Destination::Variable(name) => set_secret(ci, name, secret_value, set_env),
+        Destination::File { path, variable } => {
+            ci.mask_value(secret_value);
+
+            let written_path = ci.write_secret_file(path, secret_value)?;
+            debug!("Successfully wrote secret to '{}'", written_path.display());
+
+            if let Some(manifest_path) = ci.file_manifest_path() {
+                cleanup::record_file(&manifest_path, &written_path)?;
+            }
+
+            // The value never goes to the environment or outputs, only the path to it does
+            if let Some(variable) = variable {
+                set_variable(ci, variable, &written_path.to_string_lossy(), set_env)?;
+            }
+
+            Ok(())
+        }
+        Destination::Dotenv { prefix } => {
+            ci.mask_value(secret_value);
+
+            for (key, value) in dotenv::parse(secret_value)? {
+                let name = format!("{prefix}{key}");
+                ci.validate_variable_name(&name).map_err(|e| {
+                    anyhow::anyhow!("The dotenv secret contains an invalid name.\nError: {e}")
+                })?;
+                set_secret(ci, &name, &value, set_env)?;
+            }
+
+            Ok(())
+        }
+    }
 }
 
 /// Sets a secret in the GitHub Actions environment.
@@ -115,7 +1012,16 @@ fn set_secret<T: ContinuousIntegration>(
     set_env: bool,
 ) -> Result<()> {
     ci.mask_value(secret_value);
+    set_variable(ci, secret_name, secret_value, set_env)
+}
 
+/// Sets a value as an output and, if enabled, as an environment variable. Does not mask it.
+fn set_variable<T: ContinuousIntegration>(
+    ci: &mut T,
+    secret_name: &str,
+    secret_value: &str,
+    set_env: bool,
+) -> Result<()> {
     if set_env {
         ci.set_environment(secret_name, secret_value)?;
         debug!("Successfully wrote '{secret_name}' to environment");
@@ -129,13 +1035,19 @@ fn set_secret<T: ContinuousIntegration>(
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
+    use crate::test_server::{FakeBitwarden, Response, serve};
 
     struct FakeContinuousIntegration {
         inputs: HashMap<String, String>,
         outputs: HashMap<String, String>,
         environment: HashMap<String, String>,
         masked_values: Vec<String>,
+        files: HashMap<PathBuf, String>,
+        warnings: Vec<(String, String)>,
+        summary: Option<Vec<SummaryEntry>>,
     }
 
     impl FakeContinuousIntegration {
@@ -145,6 +1057,9 @@ mod tests {
                 outputs: HashMap::new(),
                 environment: HashMap::new(),
                 masked_values: Vec::new(),
+                files: HashMap::new(),
+                warnings: Vec::new(),
+                summary: None,
             }
         }
 
@@ -171,6 +1086,21 @@ mod tests {
         fn mask_value(&mut self, value: &str) {
             self.masked_values.push(value.to_owned());
         }
+
+        fn write_secret_file(&mut self, path: &Path, value: &str) -> Result<PathBuf> {
+            let path = Path::new("/runner/temp").join(path);
+            self.files.insert(path.clone(), value.to_owned());
+            Ok(path)
+        }
+
+        fn warning(&mut self, title: &str, message: &str) {
+            self.warnings.push((title.to_owned(), message.to_owned()));
+        }
+
+        fn write_summary(&mut self, entries: &[SummaryEntry]) -> Result<()> {
+            self.summary = Some(entries.to_vec());
+            Ok(())
+        }
     }
 
     #[test]
@@ -193,6 +1123,145 @@ mod tests {
         assert_eq!(ci.outputs.get(secret_name), Some(&secret_value.to_string()));
     }
 
+    #[tokio::test]
+    async fn test_allow_missing_retrieves_secrets_one_at_a_time() -> Result<()> {
+        let bitwarden = FakeBitwarden::new();
+        let one = Uuid::parse_str("1a2f4f7c-5d44-4bd4-9d0c-1b6f0e1c2a01")?;
+        let two = Uuid::parse_str("2b3e5a8d-6e55-4ce5-8e1d-2c7a1f2d3b02")?;
+        let (url, handle) = serve(vec![
+            bitwarden.login_response(),
+            Response::new("404 Not Found", "{}"),
+            bitwarden.secret_response(one, "one", "first value"),
+            Response::new("404 Not Found", "{}"),
+        ])
+        .await;
+
+        let mut ci = FakeContinuousIntegration::new(HashMap::from([
+            ("ACCESS_TOKEN".to_string(), bitwarden.access_token.clone()),
+            ("SECRETS".to_string(), format!("{one} > ONE\n{two} > TWO")),
+            ("API_URL".to_string(), url.clone()),
+            ("IDENTITY_URL".to_string(), url),
+            ("ALLOW_MISSING".to_string(), "true".to_string()),
+            ("RETRIES".to_string(), "0".to_string()),
+        ]));
+        set_secrets(&mut ci).await?;
+
+        assert_eq!(ci.outputs["ONE"], "first value");
+        assert_eq!(ci.outputs["TWO"], "");
+        assert!(
+            ci.warnings
+                .iter()
+                .any(|(_, message)| message.contains("TWO"))
+        );
+
+        let paths: Vec<String> = handle
+            .await?
+            .iter()
+            .map(|request| request.split(' ').nth(1).unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/connect/token".to_string(),
+                "/secrets/get-by-ids".to_string(),
+                format!("/secrets/{one}"),
+                format!("/secrets/{two}"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_secrets_are_named_without_allow_missing() -> Result<()> {
+        let bitwarden = FakeBitwarden::new();
+        let one = Uuid::parse_str("1a2f4f7c-5d44-4bd4-9d0c-1b6f0e1c2a01")?;
+        let two = Uuid::parse_str("2b3e5a8d-6e55-4ce5-8e1d-2c7a1f2d3b02")?;
+        let (url, _) = serve(vec![
+            bitwarden.login_response(),
+            Response::new("404 Not Found", "{}"),
+            bitwarden.secret_response(one, "one", "first value"),
+            Response::new("404 Not Found", "{}"),
+        ])
+        .await;
+
+        let mut ci = FakeContinuousIntegration::new(HashMap::from([
+            ("ACCESS_TOKEN".to_string(), bitwarden.access_token.clone()),
+            ("SECRETS".to_string(), format!("{one} > ONE\n{two} > TWO")),
+            ("API_URL".to_string(), url.clone()),
+            ("IDENTITY_URL".to_string(), url),
+            ("RETRIES".to_string(), "0".to_string()),
+        ]));
+        let error = set_secrets(&mut ci).await.unwrap_err();
+
+        assert!(
+            error.to_string().contains(&format!("TWO ({two})")),
+            "{error}"
+        );
+        assert!(!error.to_string().contains("ONE"), "{error}");
+        assert!(ci.outputs.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_summary_is_written_when_no_secrets_are_found() -> Result<()> {
+        let bitwarden = FakeBitwarden::new();
+        let project_id = Uuid::parse_str("4d5a7c0f-8a77-4ea7-a03f-4e9c3b4f5d04")?;
+        let (url, _) = serve(vec![
+            bitwarden.login_response(),
+            Response::new("200 OK", r#"{"secrets":[],"projects":[]}"#),
+        ])
+        .await;
+
+        let mut ci = FakeContinuousIntegration::new(HashMap::from([
+            ("ACCESS_TOKEN".to_string(), bitwarden.access_token.clone()),
+            ("SECRETS".to_string(), format!("project:{project_id}")),
+            ("API_URL".to_string(), url.clone()),
+            ("IDENTITY_URL".to_string(), url),
+            ("RETRIES".to_string(), "0".to_string()),
+        ]));
+        set_secrets(&mut ci).await?;
+
+        assert_eq!(ci.summary, Some(Vec::new()));
+        assert!(ci.outputs.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_optional_secret_default_is_used_only_when_not_found() -> Result<()> {
+        let bitwarden = FakeBitwarden::new();
+        let id = Uuid::parse_str("3c4f6b9e-7f66-4df6-9f2e-3d8b2a3e4c03")?;
+        let inputs = |url: &str| {
+            HashMap::from([
+                ("ACCESS_TOKEN".to_string(), bitwarden.access_token.clone()),
+                ("SECRETS".to_string(), format!("{id} > OPTIONAL ? fallback")),
+                ("API_URL".to_string(), url.to_string()),
+                ("IDENTITY_URL".to_string(), url.to_string()),
+                ("RETRIES".to_string(), "0".to_string()),
+            ])
+        };
+
+        let (url, _) = serve(vec![
+            bitwarden.login_response(),
+            Response::new("404 Not Found", "{}"),
+        ])
+        .await;
+        let mut ci = FakeContinuousIntegration::new(inputs(&url));
+        set_secrets(&mut ci).await?;
+        assert_eq!(ci.outputs["OPTIONAL"], "fallback");
+        assert_eq!(ci.warnings[0].0, "Default value used");
+
+        let (url, _) = serve(vec![
+            bitwarden.login_response(),
+            Response::new("403 Forbidden", "{}"),
+        ])
+        .await;
+        let mut ci = FakeContinuousIntegration::new(inputs(&url));
+        let error = set_secrets(&mut ci).await.unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::Authorization);
+        assert!(ci.outputs.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_set_secrets_with_set_env_disabled() {
         let secret_name = "TEST_SECRET";
@@ -213,48 +1282,590 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_secret_lines() {
-        let id_to_name_map = parse_secret_input(vec![
-            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
-            "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > TWO".to_string(),
-        ])
+    fn test_deliver_secret_to_file() {
+        let secret_value = "-----BEGIN TEST KEY-----\nAAAA\n-----END TEST KEY-----";
+        let destination = Destination::File {
+            path: PathBuf::from("creds/key.pem"),
+            variable: Some("KEY_PATH".to_string()),
+        };
+
+        let mut ci = FakeContinuousIntegration::default();
+
+        deliver_secret(&mut ci, &destination, secret_value, true).unwrap();
+
+        let expected_path = "/runner/temp/creds/key.pem".to_string();
+        assert_eq!(
+            ci.files.get(Path::new(&expected_path)),
+            Some(&secret_value.to_string())
+        );
+        assert_eq!(ci.environment.get("KEY_PATH"), Some(&expected_path));
+        assert_eq!(ci.outputs.get("KEY_PATH"), Some(&expected_path));
+        assert_eq!(ci.masked_values, vec![secret_value.to_string()]);
+    }
+
+    #[test]
+    fn test_parse_secret_lines_with_files() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > file:./creds/gcp.json > GCP_CREDENTIALS"
+                    .to_string(),
+                "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > file:/etc/ssl/private/tls.key".to_string(),
+            ],
+        )
         .unwrap();
 
+        assert_eq!(
+            requests
+                .ids
+                .get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
+            Some(&Destination::File {
+                path: PathBuf::from("./creds/gcp.json"),
+                variable: Some("GCP_CREDENTIALS".to_string()),
+            })
+        );
+        assert_eq!(
+            requests
+                .ids
+                .get(&Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap()),
+            Some(&Destination::File {
+                path: PathBuf::from("/etc/ssl/private/tls.key"),
+                variable: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_missing_file_path() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec!["91ba3f10-a9a2-4795-bacf-0eee2d39a074 > file:".to_string()],
+        );
+
+        assert!(requests.is_err());
+    }
+
+    #[test]
+    fn test_deliver_secret_as_dotenv() {
+        let secret_value = r#"BrowserSettings__EnvironmentUrl=https://example.com
+
+    # Browser Settings 2
+    export BrowserSettings__ApiKey="multi\nline""#;
+        let destination = Destination::Dotenv {
+            prefix: "APP_".to_string(),
+        };
+
+        let mut ci = FakeContinuousIntegration::default();
+
+        deliver_secret(&mut ci, &destination, secret_value, true).unwrap();
+
+        assert_eq!(
+            ci.environment.get("APP_BrowserSettings__EnvironmentUrl"),
+            Some(&"https://example.com".to_string())
+        );
+        assert_eq!(
+            ci.outputs.get("APP_BrowserSettings__ApiKey"),
+            Some(&"multi\nline".to_string())
+        );
+        assert!(
+            ci.masked_values
+                .contains(&"https://example.com".to_string())
+        );
+        assert!(ci.masked_values.contains(&"multi\nline".to_string()));
+        assert!(ci.masked_values.contains(&secret_value.to_string()));
+    }
+
+    #[test]
+    fn test_parse_secret_lines_with_dotenv() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > dotenv:".to_string(),
+                "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > dotenv:APP_".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            requests
+                .ids
+                .get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
+            Some(&Destination::Dotenv {
+                prefix: "".to_string()
+            })
+        );
+        assert_eq!(
+            requests
+                .ids
+                .get(&Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap()),
+            Some(&Destination::Dotenv {
+                prefix: "APP_".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines() {
+        let id_to_name_map = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
+                "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > TWO".to_string(),
+            ],
+        )
+        .unwrap()
+        .ids;
+
         assert_eq!(id_to_name_map.len(), 2);
         assert_eq!(
             id_to_name_map.get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
-            Some(&"ONE".to_string())
+            Some(&Destination::Variable("ONE".to_string()))
         );
 
         assert_eq!(
             id_to_name_map.get(&Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap()),
-            Some(&"TWO".to_string())
+            Some(&Destination::Variable("TWO".to_string()))
         );
     }
 
     #[test]
     fn test_parse_secret_lines_two() {
-        let id_to_name_map = parse_secret_input(vec![
-            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
-            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > TWO".to_string(),
-        ])
-        .unwrap();
+        let id_to_name_map = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > TWO".to_string(),
+            ],
+        )
+        .unwrap()
+        .ids;
 
         assert_eq!(id_to_name_map.len(), 1); // We expect only one entry since the UUID is the same
 
         assert_eq!(
             id_to_name_map.get(&Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap()),
-            Some(&"TWO".to_string())
+            Some(&Destination::Variable("TWO".to_string()))
         );
     }
 
     #[test]
     fn test_parse_secret_lines_invalid_uuid() {
-        let id_to_name_map = parse_secret_input(vec![
-            "invalid-uuid > INVALID".to_string(),
-            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > VALID".to_string(),
-        ]);
+        let id_to_name_map = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "invalid-uuid > INVALID".to_string(),
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > VALID".to_string(),
+            ],
+        );
 
         assert!(id_to_name_map.is_err());
     }
+
+    #[test]
+    fn test_parse_secret_lines_with_project() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "project:e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d > APP_".to_string(),
+                "project: 3c1a9b2e-6d4f-4e8a-b7c5-1a2b3c4d5e6f".to_string(),
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(requests.ids.len(), 1);
+        assert_eq!(requests.projects.len(), 2);
+        assert_eq!(
+            requests
+                .projects
+                .get(&Uuid::from_str("e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d").unwrap()),
+            Some(&"APP_".to_string())
+        );
+        assert_eq!(
+            requests
+                .projects
+                .get(&Uuid::from_str("3c1a9b2e-6d4f-4e8a-b7c5-1a2b3c4d5e6f").unwrap()),
+            Some(&"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_invalid_project_uuid() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec!["project:invalid-uuid > APP_".to_string()],
+        );
+
+        assert!(requests.is_err());
+    }
+
+    #[test]
+    fn test_name_project_secrets() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+
+        let named = name_project_secrets(
+            &FakeContinuousIntegration::default(),
+            "APP_",
+            vec![
+                (one, "DATABASE_URL".to_string()),
+                (two, "api_key".to_string()),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            named,
+            vec![
+                (one, "APP_DATABASE_URL".to_string()),
+                (two, "APP_api_key".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_name_project_secrets_rejects_invalid_keys() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+
+        let result = name_project_secrets(
+            &FakeContinuousIntegration::default(),
+            "",
+            vec![
+                (one, "database-url".to_string()),
+                (two, "1PASSWORD".to_string()),
+            ],
+        );
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("'database-url' (91ba3f10-a9a2-4795-bacf-0eee2d39a074)"));
+        assert!(error.contains("'1PASSWORD' (bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d)"));
+    }
+
+    #[test]
+    fn test_parse_secret_lines_with_keys() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "key:DATABASE_PASSWORD > DB_PASS".to_string(),
+                "key:e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d/API_KEY".to_string(),
+                "key:path/to/KEY > NESTED".to_string(),
+            ],
+        )
+        .unwrap();
+
+        assert!(requests.ids.is_empty());
+        assert_eq!(
+            requests.keys,
+            vec![
+                KeyRequest {
+                    project_id: None,
+                    key: "DATABASE_PASSWORD".to_string(),
+                    destination: Destination::Variable("DB_PASS".to_string()),
+                },
+                KeyRequest {
+                    project_id: Some(
+                        Uuid::from_str("e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d").unwrap()
+                    ),
+                    key: "API_KEY".to_string(),
+                    destination: Destination::Variable("API_KEY".to_string()),
+                },
+                KeyRequest {
+                    project_id: None,
+                    key: "path/to/KEY".to_string(),
+                    destination: Destination::Variable("NESTED".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_empty_key() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec!["key: > NAME".to_string()],
+        );
+
+        assert!(requests.is_err());
+    }
+
+    #[test]
+    fn test_resolve_secret_key() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+        let candidates = vec![(one, "ONE".to_string()), (two, "TWO".to_string())];
+
+        assert_eq!(resolve_secret_key("TWO", &candidates).unwrap(), two);
+        assert!(
+            resolve_secret_key("THREE", &candidates)
+                .unwrap_err()
+                .to_string()
+                .contains("No secret with the key 'THREE'")
+        );
+    }
+
+    #[test]
+    fn test_resolve_secret_key_ambiguous() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+        let candidates = vec![(one, "SHARED".to_string()), (two, "SHARED".to_string())];
+
+        let error = resolve_secret_key("SHARED", &candidates)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains(&one.to_string()));
+        assert!(error.contains(&two.to_string()));
+    }
+
+    #[test]
+    fn test_find_missing_secrets() {
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+        let three = Uuid::from_str("e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d").unwrap();
+
+        let id_to_name_map = HashMap::from([
+            (one, "ONE".to_string()),
+            (two, "TWO".to_string()),
+            (three, "THREE".to_string()),
+        ]);
+
+        let missing = find_missing_secrets(id_to_name_map.clone(), vec![two]);
+
+        assert_eq!(
+            missing,
+            vec![(one, "ONE".to_string()), (three, "THREE".to_string())]
+        );
+        assert_eq!(
+            describe_missing_secrets(&missing),
+            format!("ONE ({one}), THREE ({three})")
+        );
+        assert!(find_missing_secrets(id_to_name_map, vec![one, two, three]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_secret_lines_optional() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE ? fallback value".to_string(),
+                "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d > TWO ?".to_string(),
+                "e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d > THREE".to_string(),
+            ],
+        )
+        .unwrap();
+
+        let one = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+        let two = Uuid::from_str("bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d").unwrap();
+        let three = Uuid::from_str("e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d").unwrap();
+
+        assert_eq!(
+            requests.ids.get(&one),
+            Some(&Destination::Variable("ONE".to_string()))
+        );
+        assert_eq!(
+            requests.ids.get(&two),
+            Some(&Destination::Variable("TWO".to_string()))
+        );
+        assert_eq!(
+            requests.ids.get(&three),
+            Some(&Destination::Variable("THREE".to_string()))
+        );
+
+        assert_eq!(requests.defaults.len(), 2);
+        assert_eq!(
+            requests.defaults.get(&one),
+            Some(&"fallback value".to_string())
+        );
+        assert_eq!(requests.defaults.get(&two), Some(&"".to_string()));
+    }
+
+    #[test]
+    fn test_parse_secret_lines_optional_only_for_uuids() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec!["key:DATABASE_PASSWORD > DB_PASS ?".to_string()],
+        );
+
+        assert!(requests.is_err());
+    }
+
+    #[test]
+    fn test_parse_secret_lines_with_json_fields() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec![
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074#/password > DB_PASSWORD".to_string(),
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074.$.host > DB_HOST".to_string(),
+                "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > DB_CONFIG".to_string(),
+            ],
+        )
+        .unwrap();
+
+        let id = Uuid::from_str("91ba3f10-a9a2-4795-bacf-0eee2d39a074").unwrap();
+
+        assert_eq!(
+            requests.ids.get(&id),
+            Some(&Destination::Variable("DB_CONFIG".to_string()))
+        );
+        assert_eq!(
+            requests.fields,
+            vec![
+                FieldRequest {
+                    id,
+                    field: JsonField::from_pointer("/password").unwrap(),
+                    destination: Destination::Variable("DB_PASSWORD".to_string()),
+                },
+                FieldRequest {
+                    id,
+                    field: JsonField::from_path("$.host").unwrap(),
+                    destination: Destination::Variable("DB_HOST".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_lines_invalid_json_field() {
+        let requests = parse_secret_input(
+            &mut FakeContinuousIntegration::default(),
+            vec!["91ba3f10-a9a2-4795-bacf-0eee2d39a074#password > DB_PASSWORD".to_string()],
+        );
+
+        assert!(requests.is_err());
+    }
+
+    #[test]
+    fn test_summary_entry_delivery() {
+        let id = Uuid::nil();
+        let status = SecretStatus::DefaultUsed;
+        let variable = Destination::Variable("DB_PASSWORD".to_string());
+        let file = Destination::parse("file:id_rsa > KEY_PATH").unwrap();
+
+        assert_eq!(
+            variable
+                .summary_entry(id, variable.to_string(), true, status.clone())
+                .delivery,
+            Delivery::Environment
+        );
+        assert_eq!(
+            variable
+                .summary_entry(id, variable.to_string(), false, status.clone())
+                .delivery,
+            Delivery::Output
+        );
+        let entry = file.summary_entry(id, file.to_string(), true, status);
+        assert_eq!(entry.delivery, Delivery::File);
+        assert_eq!(entry.target, "file:id_rsa");
+    }
+
+    #[test]
+    fn test_write_dotenv_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("sm-action-test-{}", Uuid::new_v4()));
+        let path = dir.join(".env");
+
+        let mut fake = FakeContinuousIntegration::default();
+        let mut ci = Layered::new(&mut fake, Collecting::default());
+        let key = "-----BEGIN KEY-----\nAAAA\n-----END KEY-----";
+        deliver_secret(
+            &mut ci,
+            &Destination::Variable("KEY".to_string()),
+            key,
+            true,
+        )?;
+        deliver_secret(
+            &mut ci,
+            &Destination::Dotenv {
+                prefix: "DB_".to_string(),
+            },
+            "HOST=db.example.com\nPASSWORD='p@ss \"word\"'",
+            false,
+        )?;
+        write_dotenv_file(&mut ci, &path)?;
+
+        assert_eq!(
+            dotenv::parse(&std::fs::read_to_string(&path)?)?,
+            vec![
+                ("KEY".to_string(), key.to_string()),
+                ("DB_HOST".to_string(), "db.example.com".to_string()),
+                ("DB_PASSWORD".to_string(), "p@ss \"word\"".to_string()),
+            ]
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path)?.permissions())
+                & 0o777,
+            0o600
+        );
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_structured_output_as_step_output() -> Result<()> {
+        let mut fake = FakeContinuousIntegration::default();
+        let mut ci = Layered::new(&mut fake, Collecting::default());
+        deliver_secret(
+            &mut ci,
+            &Destination::Variable("KEY".to_string()),
+            "line1\nline2",
+            false,
+        )?;
+        write_structured_output(&mut ci, StructuredFormat::Json, None)?;
+
+        assert_eq!(
+            fake.outputs.get("json"),
+            Some(&"{\"KEY\":\"line1\\nline2\"}\n".to_string())
+        );
+        assert!(fake.masked_values.contains(&"line1\\nline2".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_secrets_are_reported_as_warnings() -> Result<()> {
+        let mut ci = FakeContinuousIntegration::default();
+        let lines = [
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > FIRST",
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > SECOND",
+            "project:b6e7d0c7-59dc-4b36-8e4b-fba3c1e0b8a1 > APP_",
+            "project:b6e7d0c7-59dc-4b36-8e4b-fba3c1e0b8a1 > OTHER_",
+        ];
+
+        parse_secret_input(&mut ci, lines.iter().map(|line| line.to_string()).collect())?;
+
+        let titles: Vec<&str> = ci
+            .warnings
+            .iter()
+            .map(|(title, _)| title.as_str())
+            .collect();
+        assert_eq!(titles, ["Duplicate secret", "Duplicate project"]);
+        assert!(ci.warnings[0].1.contains("as FIRST and SECOND"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_secret_lines_invalid_names() {
+        let mut ci = FakeContinuousIntegration::default();
+        let mut error = |lines: &[&str]| {
+            parse_secret_input(&mut ci, lines.iter().map(|line| line.to_string()).collect())
+                .unwrap_err()
+                .to_string()
+        };
+
+        let empty = error(&[
+            "91ba3f10-a9a2-4795-bacf-0eee2d39a074 > ONE",
+            "",
+            "bfd7aa33-54f2-487b-bbbf-4a69b49fdc0d >",
+        ]);
+        assert!(empty.starts_with("Line 3: "), "{empty}");
+        assert!(empty.contains("empty"), "{empty}");
+
+        let digit = error(&["91ba3f10-a9a2-4795-bacf-0eee2d39a074 > 2FA"]);
+        assert!(digit.starts_with("Line 1: "), "{digit}");
+
+        assert!(error(&["91ba3f10-a9a2-4795-bacf-0eee2d39a074 > KEY=VALUE"]).contains("'='"));
+        assert!(error(&["key:API_KEY > file:key.pem > KEY PATH"]).contains("' '"));
+        assert!(error(&["project:e5d6a1c4-7e2f-4a0b-9c3d-2f1e8b7a6c5d > 1_"]).contains("digit"));
+    }
 }
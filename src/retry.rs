@@ -0,0 +1,308 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::Result;
+use rand::Rng;
+use reqwest::StatusCode;
+
+use crate::{ci::ContinuousIntegration, timeout::ConnectTimedOut};
+
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// The SDK doesn't expose the status of failed responses, other than in its error message, which
+/// reads `Received error message from server: [<status>] <body>`.
+const SDK_RESPONSE_ERROR_PREFIX: &str = "Received error message from server: [";
+
+/// How often, and how long to wait before, retrying a request that failed for a transient reason.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+
+    /// Exponential backoff with full jitter, so that jobs which failed together don't retry
+    /// together.
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let millis = u64::try_from(ceiling.as_millis()).unwrap_or(u64::MAX);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRIES)
+    }
+}
+
+/// Whether a failed request is worth retrying.
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    /// The request may succeed if retried.
+    Transient,
+    /// Retrying would fail the same way, e.g. the access token was rejected.
+    Permanent,
+}
+
+/// Classifies an error from the SDK by the first cause that tells.
+pub fn classify(error: &anyhow::Error) -> Failure {
+    for cause in error.chain() {
+        if cause.is::<ConnectTimedOut>() {
+            return Failure::Transient;
+        }
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return match error.status() {
+                Some(status) => classify_status(status),
+                None if error.is_builder() || error.is_redirect() => Failure::Permanent,
+                None => Failure::Transient,
+            };
+        }
+        if cause.downcast_ref::<std::io::Error>().is_some() {
+            return Failure::Transient;
+        }
+        if let Some(status) = sdk_response_status(&cause.to_string()) {
+            return classify_status(status);
+        }
+    }
+    Failure::Permanent
+}
+
+/// The status of the unsuccessful response behind the error, if there was one.
+pub fn response_status(error: &anyhow::Error) -> Option<StatusCode> {
+    error.chain().find_map(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return error.status();
+        }
+        sdk_response_status(&cause.to_string())
+    })
+}
+
+fn sdk_response_status(message: &str) -> Option<StatusCode> {
+    let (_, rest) = message.split_once(SDK_RESPONSE_ERROR_PREFIX)?;
+    StatusCode::from_u16(rest.get(..3)?.parse().ok()?).ok()
+}
+
+/// Rate limiting, timeouts and server errors are transient; any other status, e.g. 401, 403 or
+/// 404, won't change on retry.
+fn classify_status(status: StatusCode) -> Failure {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT => Failure::Transient,
+        StatusCode::NOT_IMPLEMENTED | StatusCode::HTTP_VERSION_NOT_SUPPORTED => Failure::Permanent,
+        status if status.is_server_error() => Failure::Transient,
+        _ => Failure::Permanent,
+    }
+}
+
+/// Runs `operation`, retrying it as per `policy` for as long as it fails transiently. Each retry
+/// is reported as a warning through `ci`.
+pub async fn retry<T, F, Fut>(
+    ci: &mut impl ContinuousIntegration,
+    policy: &RetryPolicy,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retry = 0;
+    loop {
+        let error = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        if classify(&error) == Failure::Permanent || retry >= policy.retries {
+            return Err(error);
+        }
+
+        let delay = policy.backoff(retry);
+        retry += 1;
+        ci.warning(
+            "Retrying request",
+            &format!(
+                "Request failed, retrying in {:.1}s ({retry}/{}): {error}",
+                delay.as_secs_f64(),
+                policy.retries
+            ),
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitwarden::{Client, ClientSettings, DeviceType, auth::login::AccessTokenLoginRequest};
+
+    use super::*;
+    use crate::test_server::{Response, serve};
+
+    const ACCESS_TOKEN: &str = "0.ec2c1d46-6a4b-4751-a310-af9601317f2d.C2IgxjjLF7qSshsbwe8JGcbM075YXw:X8vbvA0bduihIDe/qrzIQQ==";
+
+    #[derive(Default)]
+    struct RecordingRunner {
+        warnings: Vec<String>,
+    }
+
+    impl ContinuousIntegration for RecordingRunner {
+        fn get_input(&self, _name: &str) -> Option<String> {
+            None
+        }
+
+        fn set_environment(&mut self, _name: &str, _value: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_output(&mut self, _name: &str, _value: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn mask_value(&mut self, _value: &str) {}
+
+        fn warning(&mut self, _title: &str, message: &str) {
+            self.warnings.push(message.to_owned());
+        }
+    }
+
+    fn fast_policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    /// Logs in with the SDK against `url`, retrying as per `policy`.
+    async fn login(ci: &mut RecordingRunner, policy: &RetryPolicy, url: &str) -> Result<()> {
+        let client = Client::new(Some(ClientSettings {
+            identity_url: url.to_string(),
+            api_url: url.to_string(),
+            user_agent: "sm-action tests".to_string(),
+            device_type: DeviceType::SDK,
+            ..Default::default()
+        }));
+        let request = AccessTokenLoginRequest {
+            access_token: ACCESS_TOKEN.to_string(),
+            state_file: None,
+        };
+
+        retry(ci, policy, || async {
+            client.auth().login_access_token(&request).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() -> Result<()> {
+        let (url, handle) = serve(vec![
+            Response::new("502 Bad Gateway", "Bad Gateway"),
+            Response::new("503 Service Unavailable", "{}"),
+            Response::new("400 Bad Request", r#"{"error":"invalid_client"}"#),
+        ])
+        .await;
+
+        // Only the rejected access token stops the retries
+        let mut ci = RecordingRunner::default();
+        let error = login(&mut ci, &fast_policy(3), &url).await.unwrap_err();
+        assert!(error.to_string().contains("invalid_client"), "{error}");
+        assert_eq!(handle.await?.len(), 3);
+        assert_eq!(ci.warnings.len(), 2);
+        assert!(ci.warnings[0].contains("(1/3)"), "{}", ci.warnings[0]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_retries() -> Result<()> {
+        let (url, handle) = serve(vec![
+            Response::new("500 Internal Server Error", "{}"),
+            Response::new("500 Internal Server Error", "{}"),
+        ])
+        .await;
+
+        let error = login(&mut RecordingRunner::default(), &fast_policy(1), &url)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("500"), "{error}");
+        assert_eq!(handle.await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_failed_connections() -> Result<()> {
+        // Nothing listens on the port once the listener is gone
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        drop(listener);
+
+        let mut ci = RecordingRunner::default();
+        let attempts = std::cell::Cell::new(0);
+        let url = &url;
+        let error = retry(&mut ci, &fast_policy(2), || {
+            attempts.set(attempts.get() + 1);
+            crate::timeout::check_connection(url, Duration::from_secs(5))
+        })
+        .await
+        .unwrap_err();
+
+        assert!(error.is::<std::io::Error>(), "{error}");
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(ci.warnings.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_permanent_errors() -> Result<()> {
+        for status in ["400 Bad Request", "401 Unauthorized", "404 Not Found"] {
+            let (url, handle) =
+                serve(vec![Response::new(status, r#"{"error":"invalid_client"}"#)]).await;
+
+            let mut ci = RecordingRunner::default();
+            assert!(login(&mut ci, &fast_policy(3), &url).await.is_err());
+            assert_eq!(handle.await?.len(), 1, "{status}");
+            assert!(ci.warnings.is_empty(), "{status}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_classify() {
+        let sdk_error = anyhow::anyhow!(
+            "Received error message from server: [503 Service Unavailable] upstream down"
+        );
+        assert_eq!(classify(&sdk_error), Failure::Transient);
+
+        let sdk_error =
+            anyhow::anyhow!("Received error message from server: [404 Not Found] not found");
+        assert_eq!(classify(&sdk_error), Failure::Permanent);
+
+        let io_error =
+            anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert_eq!(classify(&io_error), Failure::Transient);
+
+        let timed_out = anyhow::Error::new(ConnectTimedOut {
+            address: "vault.example.com:443".to_string(),
+            timeout: Duration::from_secs(30),
+        });
+        assert_eq!(classify(&timed_out), Failure::Transient);
+
+        assert_eq!(
+            classify(&anyhow::anyhow!("invalid access token")),
+            Failure::Permanent
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+
+/// A document format for providing all the secrets at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StructuredFormat {
+    Json,
+    Yaml,
+}
+
+impl FromStr for StructuredFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input.trim().to_ascii_lowercase().as_ref() {
+            "json" => Ok(StructuredFormat::Json),
+            "yaml" => Ok(StructuredFormat::Yaml),
+            _ => bail!("Unknown output format '{input}'. Expected 'json' or 'yaml'"),
+        }
+    }
+}
+
+impl fmt::Display for StructuredFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StructuredFormat::Json => write!(f, "json"),
+            StructuredFormat::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+/// Renders the variables as a single document mapping names to values, in the given order.
+///
+/// JSON is written on a single line so it also fits in CI systems with single-line outputs.
+/// YAML is written as one double-quoted scalar per line, which uses the same escapes as JSON.
+pub fn render(format: StructuredFormat, variables: &[(String, String)]) -> String {
+    match format {
+        StructuredFormat::Json => {
+            let entries: Vec<String> = variables
+                .iter()
+                .map(|(name, value)| format!("{}:{}", quote(name), quote(value)))
+                .collect();
+            format!("{{{}}}\n", entries.join(","))
+        }
+        StructuredFormat::Yaml if variables.is_empty() => "{}\n".to_string(),
+        StructuredFormat::Yaml => variables
+            .iter()
+            .map(|(name, value)| format!("{}: {}\n", quote(name), quote(value)))
+            .collect(),
+    }
+}
+
+/// The forms in which values appear escaped in a rendered document, which must be masked as
+/// well as the values themselves.
+pub fn escaped_values(variables: &[(String, String)]) -> Vec<String> {
+    variables
+        .iter()
+        .filter_map(|(_, value)| {
+            let quoted = quote(value);
+            let escaped = &quoted[1..quoted.len() - 1];
+            (escaped != value).then(|| escaped.to_string())
+        })
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Vec<(String, String)> {
+        vec![
+            ("PLAIN".to_string(), "value".to_string()),
+            ("NULL".to_string(), "null".to_string()),
+            ("KEY".to_string(), "line1\nline2 \"quoted\"".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_render_json() -> Result<()> {
+        let json = render(StructuredFormat::Json, &variables());
+
+        assert_eq!(
+            json,
+            "{\"PLAIN\":\"value\",\"NULL\":\"null\",\"KEY\":\"line1\\nline2 \\\"quoted\\\"\"}\n"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json)?,
+            serde_json::json!({ "PLAIN": "value", "NULL": "null", "KEY": "line1\nline2 \"quoted\"" })
+        );
+        assert_eq!(render(StructuredFormat::Json, &[]), "{}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_yaml() {
+        assert_eq!(
+            render(StructuredFormat::Yaml, &variables()),
+            "\"PLAIN\": \"value\"\n\"NULL\": \"null\"\n\"KEY\": \"line1\\nline2 \\\"quoted\\\"\"\n"
+        );
+        assert_eq!(render(StructuredFormat::Yaml, &[]), "{}\n");
+    }
+
+    #[test]
+    fn test_escaped_values() {
+        assert_eq!(
+            escaped_values(&variables()),
+            vec!["line1\\nline2 \\\"quoted\\\"".to_string()]
+        );
+    }
+}
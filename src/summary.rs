@@ -0,0 +1,129 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+/// What was done with one requested secret, for the job summary. Never holds the value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SummaryEntry {
+    pub id: Uuid,
+    /// Where the secret went, as written in the `secrets` input, e.g. `DB_PASSWORD` or
+    /// `file:id_rsa`.
+    pub target: String,
+    pub delivery: Delivery,
+    pub status: SecretStatus,
+}
+
+/// How a secret was made available to the job.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    /// As an environment variable and an output.
+    Environment,
+    /// As an output only.
+    Output,
+    /// Written to a file.
+    File,
+    /// In the environment of the command run by `exec` only.
+    Command,
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::Environment => write!(f, "environment and output"),
+            Delivery::Output => write!(f, "output"),
+            Delivery::File => write!(f, "file"),
+            Delivery::Command => write!(f, "command environment"),
+        }
+    }
+}
+
+/// Where the delivered value came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecretStatus {
+    /// Retrieved from Bitwarden, as last revised at this date.
+    Retrieved { revision_date: String },
+    /// An optional secret that wasn't found, so its default was used.
+    DefaultUsed,
+    /// Not returned by Bitwarden and set to an empty value, as `allow_missing` permits.
+    Missing,
+}
+
+impl fmt::Display for SecretStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretStatus::Retrieved { revision_date } => write!(f, "{revision_date}"),
+            SecretStatus::DefaultUsed => write!(f, "not found, default used"),
+            SecretStatus::Missing => write!(f, "not found, set to empty"),
+        }
+    }
+}
+
+/// Renders the entries as a Markdown table, e.g. for GitHub's job summary.
+pub fn render_markdown(entries: &[SummaryEntry]) -> String {
+    let mut markdown = String::from("### Bitwarden secrets\n\n");
+
+    if entries.is_empty() {
+        markdown.push_str("No secrets were delivered.\n");
+        return markdown;
+    }
+
+    markdown.push_str("| Secret | Target | Set as | Last revised |\n");
+    markdown.push_str("| --- | --- | --- | --- |\n");
+    for entry in entries {
+        markdown.push_str(&format!(
+            "| `{}` | {} | {} | {} |\n",
+            entry.id,
+            escape_markdown(&entry.target),
+            entry.delivery,
+            entry.status
+        ));
+    }
+    markdown
+}
+
+/// Targets are user input, so keep them from breaking out of their table cell.
+fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '|' | '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let entries = vec![
+            SummaryEntry {
+                id: Uuid::nil(),
+                target: "DB_PASSWORD".to_string(),
+                delivery: Delivery::Environment,
+                status: SecretStatus::Retrieved {
+                    revision_date: "2026-01-02 03:04:05 UTC".to_string(),
+                },
+            },
+            SummaryEntry {
+                id: Uuid::max(),
+                target: "file:keys|id_rsa".to_string(),
+                delivery: Delivery::File,
+                status: SecretStatus::Missing,
+            },
+        ];
+
+        assert_eq!(
+            render_markdown(&entries),
+            "### Bitwarden secrets\n\n\
+             | Secret | Target | Set as | Last revised |\n\
+             | --- | --- | --- | --- |\n\
+             | `00000000-0000-0000-0000-000000000000` | DB\\_PASSWORD | environment and output | 2026-01-02 03:04:05 UTC |\n\
+             | `ffffffff-ffff-ffff-ffff-ffffffffffff` | file:keys\\|id\\_rsa | file | not found, set to empty |\n"
+        );
+        assert!(render_markdown(&[]).contains("No secrets were delivered."));
+    }
+}
@@ -0,0 +1,171 @@
+//! A minimal HTTP server for tests, which replies to each request with the next scripted response.
+
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use bitwarden_crypto::{KeyEncryptable, SymmetricCryptoKey, derive_shareable_key};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// A canned HTTP response.
+pub struct Response {
+    pub status: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+        }
+    }
+}
+
+/// Serves the responses in order, one per connection, on a local port. Connections that close
+/// without a request, such as connection checks, are skipped. Returns the base URL and a handle
+/// resolving to the raw requests that were received.
+pub async fn serve(responses: Vec<Response>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+
+        for response in responses {
+            let (mut stream, request) = loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                if !request.is_empty() {
+                    break (stream, request);
+                }
+            };
+
+            let raw_response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.status,
+                response.body.len(),
+                response.body
+            );
+            stream.write_all(raw_response.as_bytes()).await.unwrap();
+
+            requests.push(String::from_utf8(request).unwrap());
+        }
+
+        requests
+    });
+
+    (url, handle)
+}
+
+/// Stands in for the Bitwarden identity and API servers. Responses are encrypted with the keys of
+/// a made-up machine account, so that the SDK can log in with `access_token` and decrypt the
+/// secrets it retrieves.
+pub struct FakeBitwarden {
+    pub access_token: String,
+    token_key: SymmetricCryptoKey,
+    organization_id: Uuid,
+    organization_key: SymmetricCryptoKey,
+}
+
+impl FakeBitwarden {
+    pub fn new() -> Self {
+        let secret = *b"sm-action-tests!";
+        let token_key = derive_shareable_key(
+            Zeroizing::new(secret),
+            "accesstoken",
+            Some("sm-access-token"),
+        );
+
+        Self {
+            access_token: format!(
+                "0.{}.client-secret:{}",
+                Uuid::new_v4(),
+                STANDARD.encode(secret)
+            ),
+            token_key: SymmetricCryptoKey::Aes256CbcHmacKey(token_key),
+            organization_id: Uuid::new_v4(),
+            organization_key: SymmetricCryptoKey::make_aes256_cbc_hmac_key(),
+        }
+    }
+
+    /// The identity server's response to logging in with `access_token`.
+    pub fn login_response(&self) -> Response {
+        let claims = json!({
+            "exp": 4102444800u64,
+            "sub": "machine-account",
+            "organization": self.organization_id,
+            "scope": ["api.secrets"],
+        });
+        let payload = json!({ "encryptionKey": self.organization_key.to_base64().to_string() });
+
+        Response::new(
+            "200 OK",
+            json!({
+                "access_token": format!("e30.{}.signature", URL_SAFE_NO_PAD.encode(claims.to_string())),
+                "expires_in": 3600,
+                "token_type": "Bearer",
+                "scope": "api.secrets",
+                "encrypted_payload": encrypt(&payload.to_string(), &self.token_key),
+            })
+            .to_string(),
+        )
+    }
+
+    /// The API's response to retrieving a single secret.
+    pub fn secret_response(&self, id: Uuid, key: &str, value: &str) -> Response {
+        Response::new("200 OK", self.secret(id, key, value).to_string())
+    }
+
+    fn secret(&self, id: Uuid, key: &str, value: &str) -> Value {
+        json!({
+            "id": id,
+            "organizationId": self.organization_id,
+            "key": encrypt(key, &self.organization_key),
+            "value": encrypt(value, &self.organization_key),
+            "note": encrypt("", &self.organization_key),
+            "creationDate": "2026-01-02T03:04:05Z",
+            "revisionDate": "2026-01-02T03:04:05Z",
+        })
+    }
+}
+
+fn encrypt(value: &str, key: &SymmetricCryptoKey) -> String {
+    value.encrypt_with_key(key).unwrap().to_string()
+}
+
+async fn read_request(stream: &mut TcpStream) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buffer).await.unwrap_or(0);
+        request.extend_from_slice(&buffer[..read]);
+        if read == 0 || is_complete_request(&request) {
+            return request;
+        }
+    }
+}
+
+fn is_complete_request(request: &[u8]) -> bool {
+    let request = String::from_utf8_lossy(request);
+    let Some((headers, body)) = request.split_once("\r\n\r\n") else {
+        return false;
+    };
+
+    let content_length = headers
+        .lines()
+        .find_map(|line| {
+            line.to_ascii_lowercase()
+                .strip_prefix("content-length:")
+                .map(|length| length.trim().parse::<usize>().unwrap_or(0))
+        })
+        .unwrap_or(0);
+    body.len() >= content_length
+}
@@ -0,0 +1,166 @@
+use std::{fmt, future::Future, time::Duration};
+
+use anyhow::{Result, bail};
+
+/// The exit code when the deadline is exceeded, as used by coreutils' `timeout`.
+pub const DEADLINE_EXCEEDED_EXIT_CODE: i32 = 124;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Variables that make the SDK connect through a proxy rather than to the server itself.
+const PROXY_VARIABLES: &[&str] = &[
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+/// Authenticating and retrieving the secrets took longer than the `timeout` input allows.
+#[derive(Debug)]
+pub struct DeadlineExceeded {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {}s retrieving secrets from Bitwarden. Please check the server is reachable, or increase 'timeout'.",
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Connecting to a server took longer than the `connect_timeout` input allows. Unlike the
+/// overall deadline, this is transient and the connection is retried.
+#[derive(Debug)]
+pub struct ConnectTimedOut {
+    pub address: String,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for ConnectTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Could not connect to {} within {}s. Please check the server is reachable, or increase 'connect_timeout'.",
+            self.address,
+            self.timeout.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for ConnectTimedOut {}
+
+/// Runs `future` to completion, or fails with `DeadlineExceeded` once `timeout` has passed.
+/// Without a timeout, it may take as long as it needs.
+pub async fn with_deadline<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(DeadlineExceeded { timeout }.into())),
+        None => future.await,
+    }
+}
+
+/// Opens a connection to the host of `url`, failing with `ConnectTimedOut` if that takes longer
+/// than `timeout`. The SDK doesn't let us limit how long its own requests take to connect, so the
+/// servers are checked this way before it's used.
+pub async fn check_connection(url: &str, timeout: Duration) -> Result<()> {
+    let url = reqwest::Url::parse(url)?;
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        bail!("{url} has no host to connect to");
+    };
+    // IPv6 addresses are bracketed in URLs, but not when connecting
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host, port))).await {
+        Ok(connection) => {
+            connection?;
+            Ok(())
+        }
+        Err(_) => Err(ConnectTimedOut {
+            address: format!("{host}:{port}"),
+            timeout,
+        }
+        .into()),
+    }
+}
+
+/// Whether the SDK connects through a proxy, in which case checking the connection to the
+/// servers themselves tells nothing.
+pub fn uses_proxy() -> bool {
+    PROXY_VARIABLES
+        .iter()
+        .any(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_deadline() -> Result<()> {
+        let error = with_deadline(
+            Some(Duration::from_millis(10)),
+            std::future::pending::<Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<DeadlineExceeded>());
+        assert!(error.to_string().contains("'timeout'"));
+
+        assert_eq!(with_deadline(None, async { Ok(1) }).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_connection() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        check_connection(&url, Duration::from_secs(5)).await?;
+
+        // Nothing listens on the port once the listener is gone
+        drop(listener);
+        let error = check_connection(&url, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(error.is::<std::io::Error>(), "{error}");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_check_connection_times_out() -> Result<()> {
+        // Once the backlog of a listener that never accepts is full, further connection attempts
+        // go unanswered, like a server that is overloaded or dropping packets
+        let socket = tokio::net::TcpSocket::new_v4()?;
+        socket.bind("127.0.0.1:0".parse()?)?;
+        let listener = socket.listen(0)?;
+        let address = listener.local_addr()?;
+        let mut queued = Vec::new();
+        for _ in 0..16 {
+            let connect = tokio::net::TcpStream::connect(address);
+            match tokio::time::timeout(Duration::from_millis(100), connect).await {
+                Ok(connection) => queued.push(connection?),
+                Err(_) => break,
+            }
+        }
+
+        let error = check_connection(&format!("http://{address}"), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        let timed_out = error.downcast_ref::<ConnectTimedOut>().unwrap();
+        assert_eq!(timed_out.address, address.to_string());
+        assert!(error.to_string().contains("'connect_timeout'"));
+        Ok(())
+    }
+}